@@ -0,0 +1,41 @@
+use std::{env, fs, path::Path};
+
+// records the version of the tree-sitter crate this build links, for
+// `--about`, as Cargo.lock has it
+fn main() {
+    let lock = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lock.display());
+    let version = fs::read_to_string(&lock)
+        .ok()
+        .and_then(|lock| tree_sitter_version(&lock))
+        .unwrap_or_else(|| "unknown".to_owned());
+    println!("cargo:rustc-env=TREE_SITTER_VERSION={version}");
+}
+
+// the lock names a dependency with its version only when several versions
+// of it are locked, otherwise the one `tree-sitter` package is it
+fn tree_sitter_version(lock: &str) -> Option<String> {
+    let packages: Vec<&str> = lock.split("[[package]]").collect();
+    let field = |package: &str, key: &str| {
+        package.lines().find_map(|line| {
+            let value = line.strip_prefix(key)?.trim().strip_prefix("= ")?;
+            Some(value.trim_matches('"').to_owned())
+        })
+    };
+    let ours = packages
+        .iter()
+        .find(|package| field(package, "name").as_deref() == Some("tree-viz"))?;
+    let dependency = ours
+        .lines()
+        .map(|line| line.trim().trim_end_matches(',').trim_matches('"'))
+        .find(|dependency| {
+            *dependency == "tree-sitter" || dependency.starts_with("tree-sitter ")
+        })?;
+    match dependency.split_once(' ') {
+        Some((_, version)) => Some(version.to_owned()),
+        None => packages
+            .iter()
+            .find(|package| field(package, "name").as_deref() == Some("tree-sitter"))
+            .and_then(|package| field(package, "version")),
+    }
+}
@@ -1,259 +1,3774 @@
-use crate::config::Config;
+use crate::{
+    config::{Config, GuideStyle, RangeFormat},
+    export,
+    keys::Action,
+    language::Registry,
+    log::verbose_enabled,
+    session::{SavedNode, Session},
+    stats::Stats,
+    verbose,
+};
 
 use std::{
-    collections::HashMap,
-    fmt::Write,
+    borrow::Cow,
+    cell::{Cell, OnceCell, RefCell},
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    fmt::{self, Write},
+    fs, io, iter,
     path::{Path, PathBuf},
+    process, thread,
+    time::{Duration, Instant},
+};
+
+use console::{style, Color, Key, Style, Term};
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE};
+use serde::{Deserialize, Serialize};
+use tree_sitter::{
+    InputEdit, Node, Parser, Point, Query, QueryCursor, Range, Tree, TreeCursor, LANGUAGE_VERSION,
+    MIN_COMPATIBLE_LANGUAGE_VERSION,
 };
 
-use console::{style, Style, Term};
-use tree_sitter::{Node, Parser, Query, QueryCursor, Range, Tree};
+// anonymous tokens that open and close a pair, in any of the grammars
+const DELIMITERS: &[(&str, &str)] = &[("(", ")"), ("[", "]"), ("{", "}"), ("<", ">")];
+
+/// Longest a single parse may take before it is abandoned, so that a
+/// pathological file cannot hang the viewer.
+const PARSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// narrowest a row is laid out for, whatever the terminal or `--width` say
+const MIN_WIDTH: usize = 20;
+
+// draws for which the nodes added by a reload stay flagged
+const CHANGED_DRAWS: usize = 3;
+
+// sources longer than this are only queried around the rows on screen
+// while drawing, exports and counts still query all of it
+const WINDOWED_QUERY_BYTES: usize = 1 << 20;
+
+/// The path given to [`App::new`] for source passed on the command line,
+/// as with `--code`, which has no file to reload.
+#[doc(hidden)]
+pub const INLINE: &str = "<code>";
 
 pub struct App {
     config: Config,
+    parser: Parser,
     language: tree_sitter::Language,
+    language_name: String,
     path: PathBuf,
-    query: Option<Query>,
-    query_path: Option<PathBuf>,
+    // compiled in the order given, those that failed are left out
+    queries: Vec<LoadedQuery>,
+    query_sources: Vec<QuerySource>,
+    // typed into the query editor, drawn in place of `query_sources`
+    // while it is not empty
+    edited_query: Option<String>,
+    // capture names drawn, all of them when `None`
+    shown_captures: Option<Vec<String>>,
+    // bytes of the source whose nodes are drawn, all of them when `None`
+    shown_range: Option<std::ops::Range<usize>>,
+    // the 1-based lines `shown_range` was given as, which end where the
+    // next line starts rather than touch it
+    shown_lines: Option<(usize, usize)>,
+    // always UTF-8, decoded from `encoding` when one is given, so byte
+    // offsets are into the decoded text
     src: Vec<u8>,
+    encoding: Option<&'static Encoding>,
+    // the file as read, before decoding, empty without an encoding
+    original: Vec<u8>,
     tree: Tree,
+    status: Option<String>,
+    // a grammar whose ABI this tree-sitter does not support, shown in the
+    // header over the tree of the one loaded instead
+    grammar_error: Option<AppError>,
+    query_error: Option<String>,
+    scroll: usize,
+    // columns hidden on the left of every row
+    hscroll: usize,
+    height: usize,
+    // columns to lay rows out for in place of the terminal width
+    width: Option<usize>,
+    selected: Option<NodeKey>,
+    // drawn with only its ancestors and its children, the whole tree is
+    // drawn when `None`
+    focus: Option<NodeKey>,
+    // how to bring the selection into view on the next draw
+    follow: Option<Follow>,
+    collapsed: HashSet<NodeKey>,
+    // first members of runs drawn in full despite `collapse_repeats`
+    open_runs: HashSet<NodeKey>,
+    // shown once above the help
+    message: Option<String>,
+    prompt: Option<(PromptKind, String)>,
+    search: Option<Search>,
+    // node kind and 0-based index among nodes of that kind, scrolled to
+    // after each reload
+    pin: Option<(String, usize)>,
+    // ERROR and MISSING nodes in the tree
+    problems: (usize, usize),
+    // nodes the last reload added, and how many it added and removed,
+    // shown for the next `changed_draws` draws
+    changed: HashSet<NodeKey>,
+    diff: (usize, usize),
+    changed_draws: usize,
+    view: View,
+    show_help: bool,
+    show_timings: bool,
+    // exports are of the selected node and what is below it
+    export_subtree: bool,
+    registry: Registry,
+    // directory mode: the files to page through, and the one shown
+    files: Vec<PathBuf>,
+    file_index: usize,
+    // files taken out of `files` as unreadable, and why
+    skipped: Vec<(PathBuf, String)>,
+    // of the last parse, and of the last query run, which happens while
+    // drawing behind a shared reference
+    parse_time: Duration,
+    query_time: Cell<Duration>,
+    // node, query index and capture index
+    captures: OnceCell<Vec<(NodeKey, usize, u32)>>,
+    // captures drawn from in a large file: those of the byte range around
+    // the rows on screen, `None` while the whole file is drawn from
+    query_window: Option<std::ops::Range<usize>>,
+    window_captures: OnceCell<Vec<(NodeKey, usize, u32)>>,
+    // kept between query runs for its allocations, the queries themselves
+    // run behind a shared reference
+    query_cursor: RefCell<QueryCursor>,
+    // byte offset of each character of the source, for character ranges
+    char_starts: OnceCell<Vec<usize>>,
+    // what the walk over the tree drew, kept until which nodes are drawn
+    // changes, and laid out again for spacing toggles
+    steps: OnceCell<Vec<Step>>,
+    // rows of the last draw, reused when only the scroll position changed
+    rows_cache: Option<Vec<(NodeKey, String)>>,
+    reuse_rows: bool,
+}
+
+#[derive(Clone, Copy)]
+enum Follow {
+    Visible,
+    Center,
+}
+
+#[derive(Clone, Copy)]
+pub enum PromptKind {
+    Search,
+    Goto,
+    Pin,
+    /// the query editor, recompiled on every key
+    Query,
+}
+
+impl PromptKind {
+    fn sigil(self) -> char {
+        match self {
+            Self::Search => '/',
+            Self::Goto => ':',
+            Self::Pin => '@',
+            Self::Query => '~',
+        }
+    }
+}
+
+/// Where a query passed to [`App::new`] comes from. Only a file is
+/// watched and re-read.
+pub enum QuerySource {
+    /// read from the file, or from stdin for `-`
+    Path(PathBuf),
+    /// passed on the command line or piped in
+    Inline(String),
+}
+
+impl QuerySource {
+    // shown in query errors
+    fn path(&self) -> &Path {
+        match self {
+            Self::Path(path) => path,
+            Self::Inline(_) => Path::new("<query>"),
+        }
+    }
+
+    // the file stem, put before capture names once there are several
+    // queries so that `highlights:keyword` and `locals:keyword` differ
+    fn namespace(&self) -> String {
+        match self {
+            Self::Path(path) => path
+                .file_stem()
+                .map_or_else(|| "query".to_owned(), |s| s.to_string_lossy().into_owned()),
+            Self::Inline(_) => "query".to_owned(),
+        }
+    }
+}
+
+struct LoadedQuery {
+    query: Query,
+    // capture names as drawn, namespaced when there are several queries
+    names: Vec<String>,
+}
+
+/// What is drawn below the header: the tree, or one of the views that
+/// take its place.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum View {
+    #[default]
+    Tree,
+    /// node kind counts
+    Stats,
+    /// the tree as one s-expression
+    Sexp,
+    /// the source with captures colored in place
+    Overlay,
+    /// the files directory mode could not show, and why
+    Skipped,
+}
+
+// an owner whose attached extras are being drawn, with what to go back
+// to once they are: its capture highlight and its run of repeats
+struct Replay<'t> {
+    owner: Node<'t>,
+    depth: i32,
+    in_capture: Option<(Range, Color)>,
+    runs: usize,
+}
+
+// a node the walk draws and where it is among those drawn, which is all
+// laying out its rows takes besides the spacing toggles
+struct Step {
+    key: NodeKey,
+    kind: &'static str,
+    range: Range,
+    named: bool,
+    extra: bool,
+    error: bool,
+    has_changes: bool,
+    // drawn level, and whether the node ends its siblings there
+    indent: usize,
+    is_last: bool,
+    field: Option<&'static str>,
+    // position among its siblings, and their number
+    sibling: Option<(usize, usize)>,
+    // color of the capture the node is inside of
+    background: Option<Color>,
+    captures: Vec<String>,
+    collapsed: bool,
+    too_deep: bool,
+    run_len: usize,
+    owner: Option<&'static str>,
+}
+
+// nodes whose kind contains the query, in tree order, and as a set for
+// underlining them while drawing
+struct Search {
+    query: String,
+    matches: Vec<NodeKey>,
+    matched: HashSet<NodeKey>,
+    current: usize,
+}
+
+// identifies a node across redraws and reparses, unlike `Node` which
+// borrows the tree it belongs to
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct NodeKey {
+    start_byte: usize,
+    end_byte: usize,
+    kind_id: u16,
+}
+
+impl NodeKey {
+    fn of(node: Node) -> Self {
+        Self {
+            start_byte: node.start_byte(),
+            end_byte: node.end_byte(),
+            kind_id: node.kind_id(),
+        }
+    }
+
+    // where the node would be after `edit`, if the edit left it intact
+    fn shifted(self, edit: &InputEdit) -> Self {
+        let shift = |byte: usize| {
+            if byte <= edit.start_byte {
+                byte
+            } else if byte >= edit.old_end_byte {
+                byte - edit.old_end_byte + edit.new_end_byte
+            } else {
+                edit.new_end_byte
+            }
+        };
+        Self {
+            start_byte: shift(self.start_byte),
+            end_byte: shift(self.end_byte),
+            kind_id: self.kind_id,
+        }
+    }
+
+    // the node this key was taken from, among those under `root`
+    fn find(self, root: Node) -> Option<Node> {
+        let mut node = root.descendant_for_byte_range(self.start_byte, self.end_byte)?;
+        while node.kind_id() != self.kind_id {
+            node = node
+                .parent()
+                .filter(|p| p.byte_range() == (self.start_byte..self.end_byte))?;
+        }
+        Some(node)
+    }
+}
+
+#[derive(Debug)]
+pub enum AppError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    QueryParse {
+        path: PathBuf,
+        offset: usize,
+        message: String,
+    },
+    /// the file went away after it was first read
+    Deleted {
+        path: PathBuf,
+    },
+    /// the file is malformed in the encoding given with `--encoding`
+    Decode {
+        path: PathBuf,
+        encoding: &'static str,
+    },
+    /// tree-sitter gave up, most likely on reaching [`PARSE_TIMEOUT`]
+    ParseFailed {
+        path: PathBuf,
+    },
+    UnsupportedLanguage {
+        name: String,
+        version: usize,
+    },
+}
+
+impl AppError {
+    #[doc(hidden)]
+    pub fn io(path: &Path, error: io::Error) -> Self {
+        Self::Io {
+            path: path.to_owned(),
+            error,
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, error } => write!(f, "unable to read {}: {error}", path.display()),
+            Self::QueryParse {
+                path,
+                offset,
+                message,
+            } => write!(
+                f,
+                "query error in {} at byte {offset}: {message}",
+                path.display()
+            ),
+            Self::Deleted { path } => {
+                write!(f, "{} deleted, showing the last parse", path.display())
+            }
+            Self::Decode { path, encoding } => {
+                write!(f, "{} is not valid {encoding}", path.display())
+            }
+            Self::ParseFailed { path } => write!(
+                f,
+                "unable to parse {} within {PARSE_TIMEOUT:?}",
+                path.display()
+            ),
+            Self::UnsupportedLanguage { name, version } => write!(
+                f,
+                "grammar `{name}` has ABI version {version}, \
+                 expected {MIN_COMPATIBLE_LANGUAGE_VERSION} to {LANGUAGE_VERSION}"
+            ),
+        }
+    }
 }
 
+impl std::error::Error for AppError {}
+
 impl App {
+    /// Parses `src` with `language`, named `language_name` as in the
+    /// [`Registry`], and runs the queries on it. `path` is the file shown
+    /// in the header, and read again when the viewer reloads. A grammar
+    /// whose ABI tree-sitter does not support is replaced by the first
+    /// built-in one that loads, and the header says so.
     pub fn new<'a, P: AsRef<Path>>(
         src: &'a [u8],
         path: P,
-        query_path: Option<P>,
+        query_sources: Vec<QuerySource>,
         language: tree_sitter::Language,
-    ) -> Self {
+        language_name: &str,
+        config: Config,
+    ) -> Result<Self, AppError> {
         let path = path.as_ref().to_owned();
 
         let mut parser = Parser::new();
-        parser.set_language(&language).unwrap();
+        // an incompatible grammar gives way to the first built-in one that
+        // loads, with the queries written for it held to what compiles
+        let mut grammar_error = None;
+        let (language, language_name) = if parser.set_language(&language).is_ok() {
+            (language, language_name.to_owned())
+        } else {
+            let error = AppError::UnsupportedLanguage {
+                name: language_name.to_owned(),
+                version: language.version(),
+            };
+            let registry = Registry::builtin();
+            let Some((name, fallback)) = registry
+                .iter()
+                .find(|(_, fallback)| parser.set_language(fallback).is_ok())
+            else {
+                return Err(error);
+            };
+            verbose!("{error}, falling back to {name}");
+            grammar_error = Some(error);
+            (fallback.clone(), name.to_owned())
+        };
 
-        let tree = parser.parse(&src, None).unwrap();
-        let query_path = query_path.map(|q| q.as_ref().to_owned());
-        let query = query_path.as_ref().map(|p| {
-            let query_src = std::fs::read_to_string(&p).expect("unable to read query");
-            Query::new(&language, &query_src).expect("query parse error")
-        });
+        parser.set_timeout_micros(PARSE_TIMEOUT.as_micros() as u64);
 
-        Self {
-            config: Default::default(),
+        let start = Instant::now();
+        let tree = parse(&mut parser, src, None, &path)?;
+        let parse_time = start.elapsed();
+        verbose!(
+            "parsed {} in {parse_time:?}: {} nodes",
+            path.display(),
+            node_count(&tree)
+        );
+
+        let problems = count_problems(&tree);
+        let mut query_error = None;
+        let queries = if grammar_error.is_none() {
+            load_queries(&language, &query_sources).collect::<Result<_, _>>()?
+        } else {
+            load_queries(&language, &query_sources)
+                .filter_map(|result| {
+                    result
+                        .inspect_err(|e| {
+                            query_error.get_or_insert_with(|| e.to_string());
+                        })
+                        .ok()
+                })
+                .collect()
+        };
+
+        Ok(Self {
+            config,
+            parser,
             path,
-            query,
-            query_path,
+            queries,
+            query_sources,
+            edited_query: None,
+            shown_captures: None,
+            shown_range: None,
+            shown_lines: None,
             src: src.to_owned(),
+            encoding: None,
+            original: Vec::new(),
             tree,
+            status: None,
+            grammar_error,
+            query_error,
+            scroll: 0,
+            hscroll: 0,
+            height: 0,
+            width: None,
+            selected: None,
+            focus: None,
+            follow: None,
+            collapsed: HashSet::new(),
+            open_runs: HashSet::new(),
+            message: None,
+            prompt: None,
+            search: None,
+            pin: None,
+            problems,
+            changed: HashSet::new(),
+            diff: (0, 0),
+            changed_draws: 0,
+            view: View::Tree,
+            show_help: true,
+            show_timings: false,
+            export_subtree: false,
+            registry: Registry::builtin(),
+            files: Vec::new(),
+            file_index: 0,
+            skipped: Vec::new(),
+            parse_time,
+            query_time: Cell::new(Duration::ZERO),
+            captures: OnceCell::new(),
+            query_window: None,
+            window_captures: OnceCell::new(),
+            query_cursor: RefCell::new(QueryCursor::new()),
+            char_starts: OnceCell::new(),
+            steps: OnceCell::new(),
+            rows_cache: None,
+            reuse_rows: false,
             language,
-        }
+            language_name,
+        })
     }
 
-    pub fn draw(&self) {
+    #[doc(hidden)]
+    pub fn draw(&mut self) {
         let term = Term::stdout();
         term.clear_screen().unwrap();
-        let mut done = false;
-        let mut depth = 0;
-        let mut in_capture: Option<Range> = None;
-        let mut cursor = self.tree.walk();
-
-        let capture_names = self
-            .query
-            .as_ref()
-            .map(|q| q.capture_names())
-            .unwrap_or_default();
-        let capture_map = self
-            .query
-            .as_ref()
-            .map(|query| {
-                QueryCursor::new()
-                    .matches(&query, self.tree.root_node(), self.src.as_slice())
-                    .flat_map(|match_| match_.captures)
-                    .fold(
-                        HashMap::new(),
-                        |mut map: HashMap<Node, Vec<u32>>, capture| {
-                            map.entry(capture.node)
-                                .and_modify(|idxs| idxs.push(capture.index))
-                                .or_insert_with(|| vec![capture.index]);
-                            map
-                        },
-                    )
-            })
-            .unwrap_or_default();
 
-        while !done {
-            let node = cursor.node();
-            let mut tree_string = String::new();
-            in_capture = match in_capture {
-                Some(range)
-                    if !contains(&range, &node.range()) && capture_map.contains_key(&node) =>
-                {
-                    Some(node.range())
+        let flag_changes = self.changed_draws > 0;
+        if flag_changes {
+            self.changed_draws -= 1;
+        } else if !self.changed.is_empty() {
+            self.changed.clear();
+            self.reuse_rows = false;
+        }
+        self.open_query_window();
+        let mut rows = match self.rows_cache.take() {
+            Some(rows) if self.reuse_rows => rows,
+            _ => self.rows(),
+        };
+        self.reuse_rows = false;
+        let help = if self.show_help {
+            self.help()
+        } else {
+            Vec::new()
+        };
+        // header, breadcrumb, footer, message line and the help
+        let term_rows = term.size().0;
+        let term_cols = self.width();
+        self.height = (term_rows as usize).saturating_sub(help.len() + 4).max(1);
+        if let Some(follow) = self.follow.take() {
+            if let Some(i) = rows.iter().position(|(key, _)| Some(*key) == self.selected) {
+                match follow {
+                    Follow::Center => self.scroll = i.saturating_sub(self.height / 2),
+                    Follow::Visible if i < self.scroll => self.scroll = i,
+                    Follow::Visible if i >= self.scroll + self.height => {
+                        self.scroll = i + 1 - self.height
+                    }
+                    Follow::Visible => (),
                 }
-                Some(range) if !contains(&range, &node.range()) => None,
-                None if capture_map.contains_key(&node) => Some(node.range()),
-                i => i,
-            };
+            }
+        }
+        self.scroll = self.scroll.min(rows.len().saturating_sub(self.height));
+        // captures do not decide which rows there are, only how they look
+        if self.update_query_window(&rows) {
+            rows = self.rows();
+        }
 
+        let mut header = String::new();
+        if self.files.len() > 1 {
             write!(
-                tree_string,
-                "{}",
-                (if in_capture.is_some() {
-                    Style::new().on_yellow().on_bright()
-                } else {
-                    Style::new()
-                })
-                .bright()
-                .black()
-                .apply_to(
-                    format!("{}{}", "|", " ".repeat(self.config.indent_level))
-                        .repeat(depth as usize)
-                )
+                header,
+                "file {}/{}: ",
+                self.file_index + 1,
+                self.files.len()
             )
             .unwrap();
+        }
+        write!(
+            header,
+            "{} {} {}",
+            style(self.display_path()).bold(),
+            self.language_name,
+            style(format!("(abi {})", self.language.version()))
+                .bright()
+                .black()
+        )
+        .unwrap();
+        if let Some(status) = &self.status {
+            write!(header, "  {}", style(status).red()).unwrap();
+        }
+        if let Some(error) = &self.grammar_error {
+            write!(header, "  {}", style(error).red()).unwrap();
+        }
+        term.write_line(&header).unwrap();
+        term.write_line(&self.breadcrumb()).unwrap();
 
-            if self.config.show_field_name {
-                if let Some(f) = cursor.field_name() {
-                    write!(
-                        tree_string,
-                        "{} ",
-                        if in_capture.is_some() {
-                            Style::new().on_yellow().on_bright()
-                        } else {
-                            Style::new()
-                        }
-                        .yellow()
-                        .apply_to(f)
-                    )
-                    .unwrap()
+        if self.view == View::Stats {
+            for line in Stats::of(&self.tree).lines(term_cols, self.height - 1) {
+                term.write_line(&line).unwrap();
+            }
+        } else if self.view == View::Skipped {
+            let lines = self
+                .skipped
+                .iter()
+                .map(|(path, reason)| format!("{}: {}", path.display(), style(reason).red()));
+            for line in lines.skip(self.scroll).take(self.height) {
+                term.write_line(&line).unwrap();
+            }
+        } else if self.view == View::Sexp {
+            let sexp = export::compact_sexp(self.tree.root_node(), &self.config);
+            let lines = wrap_words(&sexp, term_cols);
+            let start = self.scroll.min(lines.len().saturating_sub(self.height));
+            for line in lines.iter().skip(start).take(self.height) {
+                term.write_line(line).unwrap();
+            }
+        } else if self.view == View::Overlay {
+            let lines = self.overlay();
+            let start = self.scroll.min(lines.len().saturating_sub(self.height));
+            let visible = &lines[start..lines.len().min(start + self.height)];
+            let widest = visible
+                .iter()
+                .map(|line| console::measure_text_width(line))
+                .max()
+                .unwrap_or(0);
+            self.hscroll = self.hscroll.min(widest.saturating_sub(1));
+            let gutter = self.gutter_width();
+            for (i, line) in visible.iter().enumerate() {
+                let line = skip_columns(line, self.hscroll);
+                let line = console::truncate_str(&line, term_cols.saturating_sub(gutter), "");
+                if gutter == 0 {
+                    term.write_line(&line).unwrap();
+                    continue;
+                }
+                let number = format!("{:>1$} ", start + i + 1, gutter - 1);
+                term.write_line(&format!("{}{line}", style(number).dim()))
+                    .unwrap();
+            }
+        } else {
+            let visible = &rows[self.scroll..rows.len().min(self.scroll + self.height)];
+            let widest = visible
+                .iter()
+                .map(|(_, line)| console::measure_text_width(line))
+                .max()
+                .unwrap_or(0);
+            self.hscroll = self.hscroll.min(widest.saturating_sub(1));
+            let gutter = self.gutter_width();
+            for (i, (key, line)) in visible.iter().enumerate() {
+                let line = skip_columns(line, self.hscroll);
+                let line = console::truncate_str(&line, term_cols.saturating_sub(gutter), "");
+                if gutter == 0 {
+                    term.write_line(&line).unwrap();
+                    continue;
                 }
+                // continuation rows of wrapped source get no number
+                let i = self.scroll + i;
+                let number = if i > 0 && rows[i - 1].0 == *key {
+                    String::new()
+                } else {
+                    (point_at(&self.src, key.start_byte).row + 1).to_string()
+                };
+                let number = format!("{number:>0$} ", gutter - 1);
+                term.write_line(&format!("{}{line}", style(number).dim()))
+                    .unwrap();
             }
+        }
 
+        let (errors, missing) = self.problems;
+        let counts = format!("errors: {errors}  missing: {missing}");
+        let mut footer = if errors + missing > 0 {
+            style(counts).red().to_string()
+        } else {
+            style(counts).dim().to_string()
+        };
+        if self.config.toggles.show_ranges != RangeFormat::Off {
+            let ranges = format!("ranges: {}", self.config.toggles.show_ranges.name());
+            write!(footer, "  {}", style(ranges).dim()).unwrap();
+        }
+        let shown = match (&self.shown_lines, &self.shown_range) {
+            (Some((first, last)), _) => Some(format!("showing lines {first}..{last}")),
+            (None, Some(range)) => Some(format!("showing bytes {}..{}", range.start, range.end)),
+            (None, None) => None,
+        };
+        if let Some(shown) = shown {
+            write!(footer, "  {}", style(shown).dim()).unwrap();
+        }
+        if self.export_subtree {
+            write!(footer, "  {}", style("export: subtree").dim()).unwrap();
+        }
+        if self.show_timings {
+            let millis = |d: Duration| d.as_secs_f64() * 1000.0;
+            let timings = format!(
+                "parse: {:.1} ms  query: {:.1} ms  nodes: {}",
+                millis(self.parse_time),
+                millis(self.query_time.get()),
+                node_count(&self.tree)
+            );
+            write!(footer, "  {}", style(timings).dim()).unwrap();
+        }
+        if let Some(shown) = &self.shown_captures {
+            let shown = match shown.as_slice() {
+                [] => "captures: none".to_owned(),
+                names => format!("captures: @{}", names.join(" @")),
+            };
+            write!(footer, "  {}", style(shown).dim()).unwrap();
+        }
+        if !self.skipped.is_empty() {
+            let skipped = format!("skipped {} unreadable files", self.skipped.len());
+            write!(footer, "  {}", style(skipped).yellow()).unwrap();
+        }
+        if flag_changes {
+            let (added, removed) = self.diff;
+            let diff = format!("reload: {added} nodes added, {removed} removed");
+            write!(footer, "  {}", self.config.theme.changed.apply_to(diff)).unwrap();
+        }
+        if let Some(error) = &self.query_error {
+            write!(footer, "  {}", style(error).red()).unwrap();
+        }
+        if let Some(ignored) = ignored_predicates(&self.queries) {
             write!(
-                tree_string,
-                "{} ",
-                if node.is_error() {
-                    Style::new().red()
-                } else if in_capture.is_some() {
-                    Style::new().on_yellow().on_bright()
-                } else {
-                    Style::new()
-                }
-                .apply_to(node.kind()),
+                footer,
+                "  {}",
+                style(format!("ignored: {ignored}")).yellow()
             )
             .unwrap();
+        }
+        term.write_line(&footer).unwrap();
+
+        let message = match &self.prompt {
+            Some((kind, input)) => format!("{}{input}", kind.sigil()),
+            None => self.message.take().unwrap_or_default(),
+        };
+        term.write_line(&message).unwrap();
+        for line in help {
+            term.write_line(&line).unwrap();
+        }
+        self.rows_cache = Some(rows);
+    }
+
+    // kinds from the root down to the selected node
+    fn breadcrumb(&self) -> String {
+        let root = self.tree.root_node();
+        let mut node = self.selected.and_then(|key| key.find(root)).unwrap_or(root);
+        let mut path = Vec::new();
+        loop {
+            let parent = node.parent();
+            let field = parent
+                .and_then(|parent| field_of(parent, node))
+                .filter(|_| self.config.toggles.show_field_name);
+            path.push(match field {
+                Some(field) => format!("{} {}", style(field).yellow(), node.kind()),
+                None => node.kind().to_owned(),
+            });
+            match parent {
+                Some(parent) => node = parent,
+                None => break,
+            }
+        }
+        path.reverse();
+        path.join(&style(" › ").dim().to_string())
+    }
+
+    // each line lists the keys bound to its actions, lines with none bound
+    // are left out
+    fn help(&self) -> Vec<String> {
+        use Action::*;
+        let mut help: Vec<(&[Action], &str)> = vec![
+            (
+                &[IncreaseIndent, DecreaseIndent],
+                "increase/decrease indent",
+            ),
+            (
+                &[NextMatch],
+                "cycle ranges: off, bytes, chars, line:col (next match while searching)",
+            ),
+            (&[CycleRanges], "cycle ranges"),
+            (&[CycleGuides], "cycle guides: bars, tree, dots, spaces"),
+            (&[ToggleDepthGuides], "color guides by depth"),
+            (&[ToggleSource], "toggle source text"),
+            (
+                &[ToggleAnonymousText],
+                "toggle the text of anonymous nodes without source text",
+            ),
+            (
+                &[ToggleWrapSource],
+                "wrap source text instead of truncating",
+            ),
+            (
+                &[ToggleSourceLine],
+                "toggle line numbers before source text",
+            ),
+            (&[ToggleGutter], "toggle line numbers"),
+            (&[ShowLocation], "show path:line:col of the selected node"),
+            (&[CopySelected], "copy the selected node's text"),
+            (&[ToggleFieldNames], "toggle field names"),
+            (&[ToggleNodeFlags], "toggle symbol ids and node flags"),
+            (
+                &[ToggleSiblingIndex],
+                "toggle each node's index among its siblings",
+            ),
+            (&[ToggleNamedOnly], "toggle anonymous nodes"),
+            (&[ToggleAttachExtras], "attach comments"),
+            (&[ToggleCollapseRepeats], "collapse repeated siblings"),
+            (
+                &[CycleShownCaptures],
+                "cycle captures drawn: all, none, each name",
+            ),
+            (
+                &[ToggleCapturesOnly],
+                "show only captured nodes and their ancestors",
+            ),
+            (
+                &[DecreaseMaxDepth, IncreaseMaxDepth],
+                "decrease/increase max depth",
+            ),
+            (&[ScrollDown, ScrollUp, PageDown, PageUp], "scroll"),
+            (&[ScrollLeft, ScrollRight], "scroll sideways"),
+            (&[SelectNext, SelectPrev], "select node"),
+            (
+                &[SelectRoot, SelectLast],
+                "select the root (press twice)/last node",
+            ),
+            (
+                &[SelectParent, SelectFirstChild],
+                "select the parent/first child",
+            ),
+            (
+                &[SelectPrevSibling, SelectNextSibling],
+                "select the previous/next sibling",
+            ),
+            (
+                &[ToggleCollapse],
+                "collapse/expand selected node, or open a collapsed run",
+            ),
+            (
+                &[ToggleFocus],
+                "show only the selected node, its ancestors and children",
+            ),
+            (&[CycleLanguage], "switch to the next language"),
+            (&[NextError], "jump to next error"),
+            (&[ToggleStats], "toggle node kind statistics"),
+            (&[ToggleSexp], "toggle compact s-expression"),
+            (&[ToggleOverlay], "toggle the source with captures colored"),
+            (&[ToggleTimings], "toggle parse and query timings"),
+            (&[Search], "search node kinds"),
+            (&[EditQuery], "edit a query, drawn in place of the others"),
+            (&[Goto], "go to a byte offset or line:column"),
+            (&[NextMatch, PrevMatch], "next/previous match"),
+            (
+                &[Pin],
+                "pin the nth node of a kind, followed after each reload",
+            ),
+            (&[ExportSexp], "export s-expression"),
+            (&[ExportJson], "export json"),
+            (&[ExportDot], "export graphviz dot"),
+            (
+                &[ToggleSubtreeExport],
+                "toggle exporting only the selected subtree",
+            ),
+        ];
+        if !self.is_stdin() && !self.is_inline() {
+            help.push((&[Reload], "reload from disk"));
+        }
+        if self.files.len() > 1 {
+            help.push((&[PrevFile, NextFile], "previous/next file"));
+        }
+        if !self.skipped.is_empty() {
+            help.push((&[ToggleSkipped], "toggle the list of skipped files"));
+        }
+        help.push((&[ToggleHelp], "hide this help"));
+        help.push((&[Quit], "quit"));
+
+        let bindings = &self.config.keybindings;
+        help.into_iter()
+            .filter_map(|(actions, description)| {
+                let keys: Vec<_> = actions
+                    .iter()
+                    .map(|&action| bindings.keys_for(action).join(", "))
+                    .filter(|keys| !keys.is_empty())
+                    .collect();
+                (!keys.is_empty()).then(|| format!("({}) {description}", keys.join("/")))
+            })
+            .collect()
+    }
+
+    /// Renders every line of the tree exactly as `draw` prints it, before
+    /// scrolling is applied. Lines carry ANSI styling unless colors are turned
+    /// off with `console::set_colors_enabled(false)`.
+    pub fn render(&self) -> Vec<String> {
+        self.rows().into_iter().map(|(_, line)| line).collect()
+    }
+
+    /// The whole tree as [`export::plain`] lines, without styling and
+    /// whatever the width and the toggles, for snapshot tests such as
+    /// `insta::assert_snapshot!(app.render_plain().join("\n"))`.
+    pub fn render_plain(&self) -> Vec<String> {
+        export::plain(&self.tree, &self.capture_map())
+    }
+
+    // rendered lines along with the node drawn on each, laid out from the
+    // walk, which is only redone when which nodes are drawn changes
+    fn rows(&self) -> Vec<(NodeKey, String)> {
+        let term_width = self.width().saturating_sub(self.gutter_width());
+        let mut lines = Vec::new();
+        // per drawn level, whether the node last drawn there ends its siblings
+        let mut last_child: Vec<bool> = Vec::new();
+        let matching = self.matching_delimiter();
+
+        for step in self.steps.get_or_init(|| self.walk()) {
+            let key = step.key;
+            let in_capture = step.background;
+            let mut tree_string = String::new();
+
+            last_child.truncate(step.indent);
+            last_child.resize(step.indent, false);
+            last_child.push(step.is_last);
 
-            if let Some(idxs) = capture_map.get(&node) {
-                for index in idxs {
+            if self.config.toggles.depth_guides {
+                for (level, guide) in self.guide_levels(&last_child).iter().enumerate() {
+                    let color = self.config.theme.depth_color(level);
+                    let guide_style = self.config.theme.guide.clone().fg(color);
                     write!(
                         tree_string,
-                        "@{} ",
-                        style(capture_names[*index as usize]).magenta()
+                        "{}",
+                        self.themed(&guide_style, in_capture).apply_to(guide)
                     )
                     .unwrap();
                 }
+            } else {
+                write!(
+                    tree_string,
+                    "{}",
+                    self.themed(&self.config.theme.guide, in_capture)
+                        .apply_to(self.guide(&last_child))
+                )
+                .unwrap();
+            }
+
+            if self.config.toggles.show_field_name {
+                if let Some(f) = step.field {
+                    write!(
+                        tree_string,
+                        "{} ",
+                        self.themed(&self.config.theme.field, in_capture)
+                            .apply_to(f)
+                    )
+                    .unwrap()
+                }
             }
 
-            if self.config.show_ranges {
-                let range = node.range();
+            let kind_style = if step.error {
+                self.themed(&self.config.theme.error, in_capture)
+            } else if self.changed.contains(&key) {
+                self.themed(&self.config.theme.changed, in_capture)
+            } else {
+                self.themed(&Style::new(), in_capture)
+            };
+            let kind_style = if matching == Some(key) {
+                kind_style.reverse().dim()
+            } else if self
+                .search
+                .as_ref()
+                .is_some_and(|s| s.matched.contains(&key))
+            {
+                kind_style.underlined()
+            } else {
+                kind_style
+            };
+            write!(tree_string, "{} ", kind_style.apply_to(step.kind)).unwrap();
+            if self.config.toggles.show_node_flags {
+                write!(tree_string, "{} ", style(node_flags(step)).dim()).unwrap();
+            }
+            if let Some((index, count)) = step
+                .sibling
+                .filter(|_| self.config.toggles.show_sibling_index)
+            {
                 write!(
                     tree_string,
-                    " {}",
-                    style(format!("{:?}..{:?}", range.start_byte, range.end_byte,))
-                        .bright()
-                        .black()
+                    "{} ",
+                    style(format!("[{index}/{count}]")).dim()
                 )
                 .unwrap();
             }
 
-            if self.config.show_src {
+            if step.collapsed {
+                write!(tree_string, "{} ", style("{…}").dim()).unwrap();
+            } else if step.too_deep {
+                write!(tree_string, "{} ", style("…").dim()).unwrap();
+            }
+
+            if step.run_len > 1 {
                 write!(
                     tree_string,
-                    " {:.?}",
-                    style(node.utf8_text(&self.src).unwrap()).cyan()
+                    "{} ",
+                    style(format!("×{}", step.run_len)).bold()
                 )
                 .unwrap();
             }
 
-            term.write_line(&tree_string).unwrap();
-            term.clear_to_end_of_screen().unwrap();
+            if let Some(owner) = step.owner {
+                write!(tree_string, "{} ", style(format!("-> {owner}")).dim()).unwrap();
+            }
 
-            if cursor.goto_first_child() {
-                depth += 1;
-                continue;
+            for name in &step.captures {
+                write!(
+                    tree_string,
+                    "@{} ",
+                    style(name).fg(self.config.theme.capture_color(name))
+                )
+                .unwrap();
             }
-            if cursor.goto_next_sibling() {
-                continue;
+
+            let range = step.range;
+            let point = |byte: usize, point: Point| {
+                let column = display_column(&self.src, byte, self.config.tab_width);
+                format!("{}:{}", point.row + 1, column + 1)
+            };
+            let (start, end) = match self.config.toggles.show_ranges {
+                RangeFormat::Off => (String::new(), String::new()),
+                RangeFormat::Bytes => (range.start_byte.to_string(), range.end_byte.to_string()),
+                RangeFormat::Chars => (
+                    self.char_offset(range.start_byte).to_string(),
+                    self.char_offset(range.end_byte).to_string(),
+                ),
+                RangeFormat::Points => (
+                    point(range.start_byte, range.start_point),
+                    point(range.end_byte, range.end_point),
+                ),
+            };
+            // zero-width nodes, such as MISSING ones, are at a single
+            // position rather than spanning anything
+            let empty = range.start_byte == range.end_byte;
+            if self.config.toggles.show_ranges != RangeFormat::Off {
+                let range = if empty {
+                    format!("at {start}")
+                } else {
+                    format!("{start}..{end}")
+                };
+                write!(tree_string, " {}", self.config.theme.range.apply_to(range)).unwrap();
             }
 
-            loop {
-                if !cursor.goto_parent() {
-                    done = true;
-                    break;
+            let bytes = range.start_byte..range.end_byte;
+            // with the source text shown this would repeat it
+            if self.config.toggles.show_anonymous_text
+                && !self.config.toggles.show_src
+                && !step.named
+                && !empty
+            {
+                let room = term_width
+                    .saturating_sub(console::measure_text_width(&tree_string) + 3)
+                    .min(self.config.src_width);
+                let text = escape_truncated(&node_text(bytes.clone(), &self.src).0, room);
+                write!(tree_string, " {}", style(format!("\"{text}\"")).dim()).unwrap();
+            }
+
+            // wrapped source text continues on rows of its own
+            let mut continued = Vec::new();
+            if self.config.toggles.show_src && self.config.toggles.show_src_line {
+                let line = format!("{} |", range.start_point.row + 1);
+                write!(tree_string, " {}", self.config.theme.range.apply_to(line)).unwrap();
+            }
+            // an empty string is easy to miss, and there is no text to show
+            if empty {
+                write!(tree_string, " {}", style("‹empty›").dim()).unwrap();
+            }
+            if self.config.toggles.show_src && !empty {
+                let (text, lossy) = node_text(bytes, &self.src);
+                if self.config.toggles.wrap_src {
+                    let quoted: String = format!("\"{}\"", text.escape_debug());
+                    let first = term_width
+                        .saturating_sub(console::measure_text_width(&tree_string) + 1)
+                        .max(1);
+                    // past the guides and a level of indentation
+                    let margin = console::measure_text_width(&self.guide(&last_child))
+                        + self.config.toggles.indent_level
+                        + 1;
+                    let rest = term_width.saturating_sub(margin).max(1);
+                    let mut chunks = wrap_chars(&quoted, first, rest).into_iter();
+                    let chunk = chunks.next().unwrap_or_default();
+                    write!(tree_string, " {}", self.config.theme.source.apply_to(chunk)).unwrap();
+                    continued.extend(chunks.map(|chunk| {
+                        format!(
+                            "{}{}",
+                            " ".repeat(margin),
+                            self.config.theme.source.apply_to(chunk)
+                        )
+                    }));
                 } else {
-                    depth -= 1;
+                    // the quotes and the leading space take 3 columns
+                    let room = term_width
+                        .saturating_sub(console::measure_text_width(&tree_string) + 3)
+                        .min(self.config.src_width);
+                    let text = escape_truncated(&text, room);
+                    write!(
+                        tree_string,
+                        " {}",
+                        self.config.theme.source.apply_to(format!("\"{text}\""))
+                    )
+                    .unwrap();
                 }
-
-                if cursor.goto_next_sibling() {
-                    break;
+                if lossy {
+                    let last = continued.last_mut().unwrap_or(&mut tree_string);
+                    write!(
+                        last,
+                        " {}",
+                        self.config.theme.error.apply_to("(invalid utf-8)")
+                    )
+                    .unwrap();
                 }
             }
+
+            // the selection is styled alone, so that it is as easy to
+            // find on a captured or broken node as anywhere else
+            if self.selected == Some(key) {
+                let selected = |line: &str| {
+                    let plain = console::strip_ansi_codes(line);
+                    self.config.theme.selected.apply_to(plain).to_string()
+                };
+                tree_string = selected(&tree_string);
+                continued = continued.iter().map(|line| selected(line)).collect();
+            }
+            lines.push((key, tree_string));
+            lines.extend(continued.into_iter().map(|line| (key, line)));
         }
+        lines
+    }
+
+    // the nodes that are drawn, in order, along with all their rows need
+    // from the walk. filters, collapsing and the query decide these, the
+    // spacing toggles only how they are laid out
+    fn walk(&self) -> Vec<Step> {
+        let mut steps = Vec::new();
+        let mut done = false;
+        let mut depth = 0;
+        let mut in_capture: Option<(Range, Color)> = None;
+        // with extras attached, each owner is drawn first and the walk then
+        // goes back over the extras before it, drawn a level deeper as if
+        // they were its first children, before going on to its children
+        let mut replay: Option<Replay> = None;
+        // collapsed runs of identical siblings: depth and last member
+        let mut runs: Vec<(i32, Node)> = Vec::new();
+        // depths of hidden nodes above the cursor, they take up no indentation
+        let mut hidden: Vec<i32> = Vec::new();
+        // position of each node among its siblings, a parent's children
+        // at a time as the walk reaches them
+        let mut sibling_index: HashMap<Node, usize> = HashMap::new();
+        let mut cursor = self.tree.walk();
 
-        // see https://github.com/console-rs/console/issues/36#issuecomment-624731432
-        // for the reasoning behing this hackjob
+        let capture_map = self.shown_capture_map(self.drawn_captures());
+        // the focused node is drawn whatever it captures
+        let keep = self
+            .focus_filter()
+            .or_else(|| self.captures_only_filter(&capture_map));
+
+        while !done {
+            let node = cursor.node();
+            // back at the owner after its extras, on to its children
+            if let Some(r) = replay.take_if(|r| r.owner == node && r.depth == depth) {
+                in_capture = r.in_capture;
+                if cursor.goto_first_child() {
+                    depth += 1;
+                } else {
+                    done = !goto_next(&mut cursor, &mut depth);
+                }
+                continue;
+            }
+            // the owner's extras are its siblings, not more of its run
+            let owner_run = replay
+                .as_ref()
+                .is_some_and(|r| r.depth == depth && r.runs == runs.len());
+            if let Some(&(run_depth, last)) = runs.last().filter(|_| !owner_run) {
+                if run_depth == depth {
+                    runs.pop();
+                    while cursor.node() != last {
+                        cursor.goto_next_sibling();
+                    }
+                    done = !goto_next(&mut cursor, &mut depth);
+                    continue;
+                }
+            }
 
-        term.write_line("\n(>) increase indent").unwrap();
-        term.clear_to_end_of_screen().unwrap();
+            while hidden.last().is_some_and(|&d| d >= depth) {
+                hidden.pop();
+            }
+            // nothing below a node outside the filter is captured either
+            // parse errors stay visible whatever the filters, as do the
+            // nodes on the way to them
+            if !node.has_error() && keep.as_ref().is_some_and(|keep| !keep.contains(&node)) {
+                done = !goto_next(&mut cursor, &mut depth);
+                continue;
+            }
+            // nodes that only touch the range are kept, as context, but a
+            // node on the line after `--lines` is not part of them. parse
+            // errors outside the range are kept too
+            if let Some(range) = self.shown_range.as_ref().filter(|_| !node.has_error()) {
+                let after = if self.shown_lines.is_some() {
+                    node.start_byte() >= range.end
+                } else {
+                    node.start_byte() > range.end
+                };
+                if node.end_byte() < range.start || after {
+                    done = !goto_next(&mut cursor, &mut depth);
+                    continue;
+                }
+            }
+            let owner = self
+                .config
+                .toggles
+                .attach_extras
+                .then(|| owner_of_extra(node))
+                .flatten();
+            // drawn once their owner is
+            if owner.is_some() && replay.is_none() {
+                done = !goto_next(&mut cursor, &mut depth);
+                continue;
+            }
+            if self.config.hides(node) {
+                hidden.push(depth);
+                if cursor.goto_first_child() {
+                    depth += 1;
+                } else {
+                    done = !goto_next(&mut cursor, &mut depth);
+                }
+                continue;
+            }
 
-        term.write_line("(<) decrease indent ").unwrap();
-        term.clear_to_end_of_screen().unwrap();
+            let capture = capture_map
+                .get(&node)
+                .map(|names| (node.range(), self.config.theme.capture_color(names[0])));
+            in_capture = match in_capture {
+                Some((range, _)) if !contains(&range, &node.range()) && capture.is_some() => {
+                    capture
+                }
+                Some((range, _)) if !contains(&range, &node.range()) => None,
+                None => capture,
+                i => i,
+            };
 
-        term.write_line("(n) toggle ranges").unwrap();
-        term.clear_to_end_of_screen().unwrap();
+            let indent = depth - hidden.len() as i32 + replay.as_ref().map_or(0, |_| 1);
 
-        term.write_line("(s) toggle source text").unwrap();
-        term.clear_to_end_of_screen().unwrap();
+            // identical siblings following this node, drawn as one row
+            let mut run_last = node;
+            let mut run_len = 1;
+            if self.config.toggles.collapse_repeats && !self.open_runs.contains(&NodeKey::of(node))
+            {
+                while let Some(next) = run_neighbor(run_last, true) {
+                    run_last = next;
+                    run_len += 1;
+                }
+            }
 
-        term.write_line("(r) reload from disk").unwrap();
-        term.clear_to_end_of_screen().unwrap();
+            let indent = indent as usize;
+            // finding the last sibling walks the siblings that follow, once a
+            // walk rather than on every layout
+            let is_last = match owner.filter(|_| replay.as_ref().is_some_and(|r| r.depth == depth))
+            {
+                Some(owner) => self.is_last_attached(node, owner, keep.as_ref()),
+                None => self.is_last_sibling(run_last, keep.as_ref()),
+            };
+            let key = NodeKey::of(node);
+            let named = self.config.toggles.named_only;
+            let sibling = node.parent().and_then(|parent| {
+                if !sibling_index.contains_key(&node) {
+                    index_children(parent, named, &mut sibling_index);
+                }
+                let count = if named {
+                    parent.named_child_count()
+                } else {
+                    parent.child_count()
+                };
+                sibling_index.get(&node).map(|&index| (index, count))
+            });
+            let collapsed = self.collapsed.contains(&key) && node.child_count() > 0;
+            let too_deep = self
+                .config
+                .toggles
+                .max_depth
+                .is_some_and(|max| depth as usize >= max)
+                && node.child_count() > 0
+                && !node.has_error();
+            if run_len > 1 {
+                runs.push((depth, run_last));
+            }
+            steps.push(Step {
+                key,
+                kind: node.kind(),
+                range: node.range(),
+                named: node.is_named(),
+                extra: node.is_extra(),
+                error: node.is_error(),
+                has_changes: node.has_changes(),
+                indent,
+                is_last,
+                field: cursor.field_name(),
+                sibling,
+                background: in_capture.map(|(_, color)| color),
+                captures: capture_map.get(&node).map_or_else(Vec::new, |names| {
+                    names.iter().map(|name| name.to_string()).collect()
+                }),
+                collapsed,
+                too_deep,
+                run_len,
+                owner: owner.map(|owner| owner.kind()),
+            });
 
-        term.write_line("(C-c) quit").unwrap();
-        term.clear_to_end_of_screen().unwrap();
+            let first_extra = self
+                .config
+                .toggles
+                .attach_extras
+                .then(|| first_attached_extra(node))
+                .flatten();
+            if let Some(first) = first_extra.filter(|_| !collapsed && !too_deep) {
+                replay = Some(Replay {
+                    owner: node,
+                    depth,
+                    in_capture,
+                    runs: runs.len(),
+                });
+                // a cursor only steps forward, so it starts over from the
+                // first sibling
+                cursor.goto_parent();
+                cursor.goto_first_child();
+                while cursor.node() != first {
+                    cursor.goto_next_sibling();
+                }
+                continue;
+            }
+            if !collapsed && !too_deep && cursor.goto_first_child() {
+                depth += 1;
+                continue;
+            }
+            done = !goto_next(&mut cursor, &mut depth);
+        }
+        steps
     }
 
-    pub fn increase_indent(&mut self) {
-        self.config.indent_level = self.config.indent_level.saturating_add(1);
+    // characters before `byte`, each invalid UTF-8 byte counting as one
+    fn char_offset(&self, byte: usize) -> usize {
+        let starts = self.char_starts.get_or_init(|| {
+            (0..self.src.len())
+                .filter(|&i| self.src[i] & 0xc0 != 0x80)
+                .collect()
+        });
+        starts.partition_point(|&start| start < byte)
     }
 
-    pub fn decrease_indent(&mut self) {
-        self.config.indent_level = self.config.indent_level.saturating_sub(1);
+    /// Marks the source as decoded from `encoding` with [`decode`], from
+    /// the bytes `original`. Reloads and the other files of a directory
+    /// are decoded the same way.
+    #[doc(hidden)]
+    pub fn with_encoding(mut self, encoding: &'static Encoding, original: Vec<u8>) -> Self {
+        self.encoding = Some(encoding);
+        self.original = original;
+        self
     }
 
-    pub fn toggle_ranges(&mut self) {
-        self.config.show_ranges = !self.config.show_ranges;
+    /// Where the UTF-8 offset `byte` of the source is in the file as it
+    /// was read, before decoding. The same offset without an encoding.
+    fn original_offset(&self, byte: usize) -> usize {
+        let Some(encoding) = self.encoding else {
+            return byte;
+        };
+        let bom = match Encoding::for_bom(&self.original) {
+            Some((bom_encoding, len)) if bom_encoding == encoding => len,
+            _ => 0,
+        };
+        let text = String::from_utf8_lossy(&self.src[..byte]);
+        // encoding_rs only encodes UTF-16 as UTF-8
+        let len = if encoding == UTF_16LE || encoding == UTF_16BE {
+            text.encode_utf16().count() * 2
+        } else {
+            encoding.encode(&text).0.len()
+        };
+        bom + len
     }
 
-    pub fn toggle_source(&mut self) {
-        self.config.show_src = !self.config.show_src;
+    /// Draws only the nodes that overlap the bytes `range` of the source,
+    /// or touch it, and selects the node where it starts. The whole source
+    /// is still parsed.
+    pub fn with_range(mut self, range: std::ops::Range<usize>) -> Self {
+        let start = range.start.min(self.src.len());
+        let end = range.end.clamp(start, self.src.len());
+        let root = self.tree.root_node();
+        if let Some(node) = root.descendant_for_byte_range(start, start) {
+            self.jump_to(NodeKey::of(node));
+        }
+        self.shown_range = Some(start..end);
+        self.steps = OnceCell::new();
+        self
     }
 
+    /// Like [`App::with_range`], for the 1-based lines `first` to `last`,
+    /// both included. Nodes starting on the line after `last` are left out.
+    pub fn with_lines(self, first: usize, last: usize) -> Self {
+        let line_start = |line: usize| {
+            if line <= 1 {
+                return 0;
+            }
+            self.src
+                .iter()
+                .enumerate()
+                .filter(|&(_, &b)| b == b'\n')
+                .nth(line - 2)
+                .map_or(self.src.len(), |(i, _)| i + 1)
+        };
+        let range = line_start(first)..line_start(last + 1);
+        let mut app = self.with_range(range);
+        app.shown_lines = Some((first, last));
+        app.steps = OnceCell::new();
+        app
+    }
+
+    /// Lays rows out for `columns` instead of the terminal width, so that
+    /// output does not depend on where it is printed. Clamped to at least
+    /// 20 columns.
+    pub fn with_width(mut self, columns: usize) -> Self {
+        self.width = Some(columns);
+        self
+    }
+
+    fn width(&self) -> usize {
+        self.width
+            .unwrap_or_else(|| Term::stdout().size().1 as usize)
+            .max(MIN_WIDTH)
+    }
+
+    // a theme style, on the background of the enclosing capture if any
+    fn themed(&self, base: &Style, in_capture: Option<Color>) -> Style {
+        match in_capture {
+            Some(color) => base.clone().bg(color).on_bright(),
+            None => base.clone(),
+        }
+    }
+
+    // line numbers and the space after them
+    fn gutter_width(&self) -> usize {
+        if !self.config.toggles.show_gutter {
+            return 0;
+        }
+        let lines = self.src.iter().filter(|&&b| b == b'\n').count() + 1;
+        lines.to_string().len() + 1
+    }
+
+    // indentation for a node, given which of the nodes on its path end
+    // their siblings, the root first and the node itself last
+    fn guide(&self, last_child: &[bool]) -> String {
+        self.guide_levels(last_child).concat()
+    }
+
+    // the guide split into its levels, outermost first, so that each can
+    // be colored by depth
+    fn guide_levels(&self, last_child: &[bool]) -> Vec<String> {
+        let fill = self.config.toggles.indent_level;
+        let Some((&is_last, ancestors)) = last_child.split_last() else {
+            return Vec::new();
+        };
+        // the root never has siblings, and takes up no indentation
+        let ancestors = ancestors.get(1..).unwrap_or_default();
+        let levels = last_child.len() - 1;
+        let level = |c: char| format!("{c}{}", " ".repeat(fill));
+        match self.config.toggles.guide_style {
+            GuideStyle::Bars => vec![level('|'); levels],
+            GuideStyle::Spaces => vec![level(' '); levels],
+            GuideStyle::Dots => vec![level('·'); levels],
+            GuideStyle::Tree => {
+                if levels == 0 {
+                    return Vec::new();
+                }
+                let mut guide: Vec<_> = ancestors
+                    .iter()
+                    .map(|&last| level(if last { ' ' } else { '│' }))
+                    .collect();
+                guide.push(format!(
+                    "{}{}",
+                    if is_last { '└' } else { '├' },
+                    "─".repeat(fill)
+                ));
+                guide
+            }
+        }
+    }
+
+    // whether nothing is drawn after an extra under the owner it is
+    // attached to, neither another extra nor one of the owner's children
+    fn is_last_attached(&self, node: Node, owner: Node, keep: Option<&HashSet<Node>>) -> bool {
+        let mut next = node.next_sibling();
+        while let Some(sibling) = next.filter(|&sibling| sibling != owner) {
+            if !self.is_hidden(sibling, keep) {
+                return false;
+            }
+            next = sibling.next_sibling();
+        }
+        owner.child(0).is_none_or(|child| {
+            !self.is_drawn_in_place(child, keep) && self.is_last_sibling(child, keep)
+        })
+    }
+
+    // whether nothing is drawn after this node among its siblings. extras
+    // attached to an owner are drawn under it rather than here
+    fn is_last_sibling(&self, node: Node, keep: Option<&HashSet<Node>>) -> bool {
+        let mut next = node.next_sibling();
+        while let Some(sibling) = next {
+            if self.is_drawn_in_place(sibling, keep) {
+                return false;
+            }
+            next = sibling.next_sibling();
+        }
+        true
+    }
+
+    // drawn among its siblings, neither hidden nor attached to an owner
+    fn is_drawn_in_place(&self, node: Node, keep: Option<&HashSet<Node>>) -> bool {
+        let attached = self.config.toggles.attach_extras && owner_of_extra(node).is_some();
+        !attached && !self.is_hidden(node, keep)
+    }
+
+    // left out of the rows by named-only mode or the captures-only filter
+    fn is_hidden(&self, node: Node, keep: Option<&HashSet<Node>>) -> bool {
+        self.config.hides(node)
+            || (!node.has_error() && keep.is_some_and(|keep| !keep.contains(&node)))
+    }
+
+    // the other half of the selected bracket, among its siblings
+    fn matching_delimiter(&self) -> Option<NodeKey> {
+        let node = self.selected?.find(self.tree.root_node())?;
+        if node.is_named() {
+            return None;
+        }
+        let (open, close, forward) = DELIMITERS.iter().find_map(|&(open, close)| {
+            if node.kind() == open {
+                Some((open, close, true))
+            } else if node.kind() == close {
+                Some((open, close, false))
+            } else {
+                None
+            }
+        })?;
+        let step = |n: Node<'_>| {
+            if forward {
+                n.next_sibling()
+            } else {
+                n.prev_sibling()
+            }
+        };
+        // count nesting so that `(()` pairs up its inner brackets first
+        let mut depth = 0;
+        let mut next = step(node);
+        while let Some(sibling) = next {
+            let kind = sibling.kind();
+            if (kind == open && forward) || (kind == close && !forward) {
+                depth += 1;
+            } else if (kind == close && forward) || (kind == open && !forward) {
+                if depth == 0 {
+                    return Some(NodeKey::of(sibling));
+                }
+                depth -= 1;
+            }
+            next = step(sibling);
+        }
+        None
+    }
+
+    // with the filter on and a query loaded, the captured nodes and their
+    // ancestors are the only ones drawn
+    fn captures_only_filter<'t>(
+        &self,
+        capture_map: &HashMap<Node<'t>, Vec<&str>>,
+    ) -> Option<HashSet<Node<'t>>> {
+        if !self.config.toggles.captures_only || self.queries.is_empty() {
+            return None;
+        }
+        let mut keep = HashSet::new();
+        for &node in capture_map.keys() {
+            let mut ancestor = Some(node);
+            while let Some(node) = ancestor {
+                if !keep.insert(node) {
+                    break;
+                }
+                ancestor = node.parent();
+            }
+        }
+        Some(keep)
+    }
+
+    // while focused, the focused node, its ancestors and its children are
+    // the only ones drawn, until a reload takes the node away
+    fn focus_filter(&self) -> Option<HashSet<Node<'_>>> {
+        let node = self.focus?.find(self.tree.root_node())?;
+        let mut keep: HashSet<_> = node.children(&mut node.walk()).collect();
+        let mut ancestor = Some(node);
+        while let Some(node) = ancestor {
+            keep.insert(node);
+            ancestor = node.parent();
+        }
+        Some(keep)
+    }
+
+    // the captures that are drawn, `capture_map` without those left out
+    // with `--captures`
+    fn shown_capture_map<'a>(
+        &'a self,
+        captures: &[(NodeKey, usize, u32)],
+    ) -> HashMap<Node<'a>, Vec<&'a str>> {
+        let mut capture_map = self.map_captures(captures);
+        if let Some(shown) = &self.shown_captures {
+            capture_map.retain(|_, names| {
+                names.retain(|name| shown.iter().any(|shown| shown == name));
+                !names.is_empty()
+            });
+        }
+        capture_map
+    }
+
+    /// Renders the source itself, one line per line of the file, with the
+    /// text of each capture in the color of its name, as a highlight query
+    /// would show it in an editor. Nested captures are drawn over the ones
+    /// around them and tabs are expanded to `tab_width`.
+    #[doc(hidden)]
+    pub fn overlay(&self) -> Vec<String> {
+        let mut captured: Vec<_> = self
+            .shown_capture_map(self.captures())
+            .into_iter()
+            .map(|(node, names)| {
+                let depth = std::iter::successors(node.parent(), Node::parent).count();
+                let color = self.config.theme.capture_color(names[0]);
+                (node.byte_range(), depth, color)
+            })
+            .collect();
+        captured.sort_by_key(|(range, depth, _)| (range.start, Reverse(range.end), *depth));
+        let mut colors = vec![None; self.src.len()];
+        for (range, _, color) in captured {
+            colors[range].fill(Some(color));
+        }
+
+        let mut lines = Vec::new();
+        let mut line_start = 0;
+        for line in self.src.split(|&b| b == b'\n') {
+            let text = line.strip_suffix(b"\r").unwrap_or(line);
+            let line_colors = &colors[line_start..line_start + text.len()];
+            line_start += line.len() + 1;
+
+            let mut out = String::new();
+            let mut column = 0;
+            let mut run_start = 0;
+            while run_start < text.len() {
+                let color = line_colors[run_start];
+                let run_len = line_colors[run_start..]
+                    .iter()
+                    .take_while(|&&c| c == color)
+                    .count();
+                let mut run = String::new();
+                for c in String::from_utf8_lossy(&text[run_start..run_start + run_len]).chars() {
+                    let next = advance(column, c, self.config.tab_width);
+                    match c {
+                        '\t' => run.push_str(&" ".repeat(next - column)),
+                        c => run.push(c),
+                    }
+                    column = next;
+                }
+                match color {
+                    Some(color) => write!(out, "{}", style(run).fg(color)).unwrap(),
+                    None => out.push_str(&run),
+                }
+                run_start += run_len;
+            }
+            lines.push(out);
+        }
+        // no line after the final newline
+        if self.src.ends_with(b"\n") {
+            lines.pop();
+        }
+        lines
+    }
+
+    // names of the query captures on each captured node, of every query
+    fn capture_map(&self) -> HashMap<Node<'_>, Vec<&str>> {
+        self.map_captures(self.captures())
+    }
+
+    fn map_captures(&self, captures: &[(NodeKey, usize, u32)]) -> HashMap<Node<'_>, Vec<&str>> {
+        let mut map: HashMap<Node, Vec<&str>> = HashMap::new();
+        let root = self.tree.root_node();
+        for &(key, query, index) in captures {
+            if let Some(node) = key.find(root) {
+                let name = &self.queries[query].names[index as usize];
+                map.entry(node).or_default().push(name);
+            }
+        }
+        map
+    }
+
+    // running the queries is the slow part of drawing, so their result is
+    // kept until the tree or the queries change. matches failing `#eq?`,
+    // `#match?` or `#any-of?` are dropped by the cursor itself, checked
+    // against the source text
+    fn captures(&self) -> &[(NodeKey, usize, u32)] {
+        self.captures
+            .get_or_init(|| self.query_range(0..usize::MAX))
+    }
+
+    // the captures rows are drawn with, only those of the query window
+    // when there is one
+    fn drawn_captures(&self) -> &[(NodeKey, usize, u32)] {
+        match &self.query_window {
+            Some(window) => self
+                .window_captures
+                .get_or_init(|| self.query_range(window.clone())),
+            None => self.captures(),
+        }
+    }
+
+    // the captures of every query's matches that intersect `range`
+    fn query_range(&self, range: std::ops::Range<usize>) -> Vec<(NodeKey, usize, u32)> {
+        let mut captures = Vec::new();
+        // to the cursor an empty range is no bound at all
+        if range.is_empty() {
+            return captures;
+        }
+        let start = Instant::now();
+        let mut cursor = self.query_cursor.borrow_mut();
+        cursor.set_byte_range(range);
+        for (i, loaded) in self.queries.iter().enumerate() {
+            captures.extend(
+                cursor
+                    .matches(&loaded.query, self.tree.root_node(), self.src.as_slice())
+                    .flat_map(|match_| match_.captures)
+                    .map(|capture| (NodeKey::of(capture.node), i, capture.index)),
+            );
+        }
+        cursor.set_byte_range(0..usize::MAX);
+        self.query_time.set(start.elapsed());
+        captures
+    }
+
+    // the captures-only filter needs every capture, so there is no window
+    // with it on
+    fn is_windowed(&self) -> bool {
+        self.src.len() > WINDOWED_QUERY_BYTES
+            && !self.config.toggles.captures_only
+            && !self.queries.is_empty()
+    }
+
+    // before the rows are first laid out there is nothing to place the
+    // window over, so they start with an empty one rather than a query of
+    // the whole file, and `update_query_window` then moves it
+    fn open_query_window(&mut self) {
+        if self.query_window.is_none() && self.is_windowed() {
+            self.query_window = Some(0..0);
+            self.window_captures = OnceCell::new();
+            self.steps = OnceCell::new();
+        }
+    }
+
+    // in a large file, moves the query window over the rows that are on
+    // screen or a screen away from it, once scrolling leaves the window.
+    // true when the rows have to be drawn again
+    fn update_query_window(&mut self, rows: &[(NodeKey, String)]) -> bool {
+        if !self.is_windowed() {
+            if self.query_window.take().is_none() {
+                return false;
+            }
+            self.steps = OnceCell::new();
+            return true;
+        }
+        let first = self.scroll.saturating_sub(self.height);
+        let last = (self.scroll + 2 * self.height).min(rows.len());
+        let starts = rows[first.min(last)..last]
+            .iter()
+            .map(|(key, _)| key.start_byte);
+        let (Some(start), Some(end)) = (starts.clone().min(), starts.max()) else {
+            return false;
+        };
+        // a row's own matches need only intersect where it starts
+        let wanted = start..end + 1;
+        if self
+            .query_window
+            .as_ref()
+            .is_some_and(|window| window.start <= wanted.start && wanted.end <= window.end)
+        {
+            return false;
+        }
+        verbose!("querying bytes {}..{}", wanted.start, wanted.end);
+        self.query_window = Some(wanted);
+        self.window_captures = OnceCell::new();
+        self.steps = OnceCell::new();
+        true
+    }
+
+    /// Draws only the captures with these names, labels and highlighting
+    /// alike. Exports still include every capture.
+    #[doc(hidden)]
+    pub fn with_shown_captures(mut self, names: Vec<String>) -> Self {
+        self.shown_captures = Some(names);
+        self.steps = OnceCell::new();
+        self
+    }
+
+    // all captures, then none, then each capture name on its own
+    #[doc(hidden)]
+    pub fn cycle_shown_captures(&mut self) {
+        let mut names: Vec<&str> = Vec::new();
+        for name in self.queries.iter().flat_map(|loaded| &loaded.names) {
+            if !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+        self.shown_captures = match self.shown_captures.as_deref() {
+            None => Some(Vec::new()),
+            Some([]) => names.first().map(|name| vec![name.to_string()]),
+            Some([current]) => names
+                .iter()
+                .position(|name| name == current)
+                .and_then(|i| names.get(i + 1))
+                .map(|name| vec![name.to_string()]),
+            Some(_) => None,
+        };
+        self.steps = OnceCell::new();
+    }
+
+    /// Number of query matches and of captures under each capture name, in
+    /// the order the queries declare them. `None` without a query.
+    #[doc(hidden)]
+    pub fn query_counts(&self) -> Option<(usize, Vec<(&str, usize)>)> {
+        if self.queries.is_empty() {
+            return None;
+        }
+        let mut counts = Vec::new();
+        let mut matches = 0;
+        for loaded in &self.queries {
+            let first = counts.len();
+            counts.extend(loaded.names.iter().map(|name| (name.as_str(), 0)));
+            let root = self.tree.root_node();
+            let mut cursor = self.query_cursor.borrow_mut();
+            for match_ in cursor.matches(&loaded.query, root, self.src.as_slice()) {
+                matches += 1;
+                for capture in match_.captures {
+                    counts[first + capture.index as usize].1 += 1;
+                }
+            }
+        }
+        Some((matches, counts))
+    }
+
+    /// Parse and query cost without drawing: the source size, parse and
+    /// query times, node count and depth, one `name: value` per line. Runs
+    /// the queries if they have not run yet.
+    #[doc(hidden)]
+    pub fn stats_report(&self) -> Vec<String> {
+        let captures = self.captures().len();
+        let stats = Stats::of(&self.tree);
+        let millis = |d: Duration| d.as_secs_f64() * 1000.0;
+        let (errors, missing) = self.problems;
+        let mut report = vec![
+            format!("source: {} bytes", self.src.len()),
+            format!("parse: {:.1} ms", millis(self.parse_time)),
+        ];
+        if !self.queries.is_empty() {
+            report.push(format!(
+                "query: {:.1} ms, {captures} captures",
+                millis(self.query_time.get())
+            ));
+        }
+        report.extend([
+            format!("nodes: {}", stats.nodes),
+            format!("kinds: {}", stats.kinds.len()),
+            format!("max depth: {}", stats.max_depth),
+            format!("errors: {errors}  missing: {missing}"),
+        ]);
+        report
+    }
+
+    pub fn export(&self, format: export::Format, out: &mut dyn io::Write) -> io::Result<()> {
+        export::write(
+            format,
+            self.export_root(),
+            &self.src,
+            &self.config,
+            &self.capture_map(),
+            out,
+        )
+    }
+
+    // the selected node when exporting a subtree, otherwise the root
+    fn export_root(&self) -> Node<'_> {
+        if self.export_subtree {
+            self.selected_node()
+        } else {
+            self.tree.root_node()
+        }
+    }
+
+    /// Exports next to the source file, as `<path>.<extension>`, or as
+    /// `<path>.<kind>.<extension>` for the subtree of a selected node.
+    #[doc(hidden)]
+    pub fn export_to_file(&mut self, format: export::Format) {
+        let mut path = if self.is_stdin() {
+            PathBuf::from("stdin").into_os_string()
+        } else {
+            self.path.clone().into_os_string()
+        };
+        let root = self.export_root();
+        if root != self.tree.root_node() {
+            path.push(".");
+            path.push(root.kind());
+        }
+        path.push(".");
+        path.push(format.extension());
+        let path = PathBuf::from(path);
+
+        let result = fs::File::create(&path).and_then(|file| {
+            let mut out = io::BufWriter::new(file);
+            self.export(format, &mut out)?;
+            io::Write::flush(&mut out)
+        });
+        self.message = Some(match result {
+            Ok(()) => format!("wrote {}", path.display()),
+            Err(e) => format!("unable to write {}: {e}", path.display()),
+        });
+    }
+
+    #[doc(hidden)]
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.reuse_rows = true;
+        // clamped against the tree height on the next draw
+        self.scroll = self.scroll.saturating_add(lines);
+    }
+
+    #[doc(hidden)]
+    pub fn scroll_up(&mut self, lines: usize) {
+        self.reuse_rows = true;
+        self.scroll = self.scroll.saturating_sub(lines);
+    }
+
+    #[doc(hidden)]
+    pub fn select_next(&mut self) {
+        self.move_selection(1);
+    }
+
+    #[doc(hidden)]
+    pub fn select_prev(&mut self) {
+        self.move_selection(-1);
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let mut keys: Vec<_> = self.rows().into_iter().map(|(key, _)| key).collect();
+        // a node with wrapped source spans several rows
+        keys.dedup();
+        let next = match keys.iter().position(|key| Some(*key) == self.selected) {
+            Some(i) => i.saturating_add_signed(delta).min(keys.len() - 1),
+            None => 0,
+        };
+        self.selected = keys.get(next).copied();
+        self.follow = Some(Follow::Visible);
+    }
+
+    #[doc(hidden)]
+    pub fn select_root(&mut self) {
+        self.select(NodeKey::of(self.tree.root_node()));
+    }
+
+    #[doc(hidden)]
+    pub fn select_last(&mut self) {
+        let last = descendants(self.tree.root_node())
+            .into_iter()
+            .rev()
+            .find(|node| !self.config.hides(*node));
+        if let Some(node) = last {
+            self.select(NodeKey::of(node));
+        }
+    }
+
+    // the nearest ancestor that is drawn
+    #[doc(hidden)]
+    pub fn select_parent(&mut self) {
+        let mut parent = self.selected_node().parent();
+        while let Some(node) = parent.filter(|node| self.config.hides(*node)) {
+            parent = node.parent();
+        }
+        if let Some(node) = parent {
+            self.select(NodeKey::of(node));
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn select_first_child(&mut self) {
+        let mut child = self.selected_node().child(0);
+        while let Some(node) = child.filter(|node| self.config.hides(*node)) {
+            child = node.next_sibling();
+        }
+        if let Some(node) = child {
+            self.select(NodeKey::of(node));
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn select_next_sibling(&mut self) {
+        self.select_sibling(|node| node.next_sibling());
+    }
+
+    #[doc(hidden)]
+    pub fn select_prev_sibling(&mut self) {
+        self.select_sibling(|node| node.prev_sibling());
+    }
+
+    fn select_sibling(&mut self, step: impl Fn(Node) -> Option<Node>) {
+        let mut sibling = step(self.selected_node());
+        while let Some(node) = sibling.filter(|node| self.config.hides(*node)) {
+            sibling = step(node);
+        }
+        if let Some(node) = sibling {
+            self.select(NodeKey::of(node));
+        }
+    }
+
+    // structural motions start from the root when nothing is selected. a
+    // `TreeCursor` cannot be kept across draws as it borrows the tree, so
+    // they walk from the node the selection is found at instead
+    fn selected_node(&self) -> Node<'_> {
+        let root = self.tree.root_node();
+        self.selected.and_then(|key| key.find(root)).unwrap_or(root)
+    }
+
+    // like `jump_to`, but only scrolls as far as needed
+    fn select(&mut self, key: NodeKey) {
+        self.jump_to(key);
+        self.follow = Some(Follow::Visible);
+    }
+
+    /// Selects the node drawn on a row of the screen, counted from 0 at the
+    /// top, as when it is clicked. Rows outside the tree are ignored.
+    #[doc(hidden)]
+    pub fn select_row(&mut self, row: usize) {
+        // below the header and the breadcrumb
+        let Some(row) = row.checked_sub(2).filter(|&row| row < self.height) else {
+            return;
+        };
+        if self.view != View::Tree {
+            return;
+        }
+        let key = self
+            .rows_cache
+            .as_ref()
+            .and_then(|rows| rows.get(self.scroll + row))
+            .map(|(key, _)| *key);
+        if key.is_some() {
+            self.selected = key;
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn is_prompting(&self) -> bool {
+        self.prompt.is_some()
+    }
+
+    #[doc(hidden)]
+    pub fn open_prompt(&mut self, kind: PromptKind) {
+        let input = match kind {
+            PromptKind::Query => self.edited_query.clone().unwrap_or_default(),
+            _ => String::new(),
+        };
+        self.prompt = Some((kind, input));
+    }
+
+    #[doc(hidden)]
+    pub fn prompt_key(&mut self, key: Key) {
+        let Some((_, input)) = &mut self.prompt else {
+            return;
+        };
+        let edited = match key {
+            Key::Char(c) => {
+                input.push(c);
+                true
+            }
+            Key::Backspace => input.pop().is_some(),
+            Key::Enter => {
+                let (kind, input) = self.prompt.take().unwrap();
+                match kind {
+                    PromptKind::Search => self.search(input),
+                    PromptKind::Goto => self.goto(&input),
+                    PromptKind::Pin => self.pin(&input),
+                    PromptKind::Query => (),
+                }
+                false
+            }
+            Key::Escape => {
+                self.prompt = None;
+                false
+            }
+            _ => false,
+        };
+        // the editor has no enter step, what is typed is what is drawn
+        if let Some((PromptKind::Query, query)) = self.prompt.as_ref().filter(|_| edited) {
+            let query = query.clone();
+            self.edit_query(query);
+        }
+    }
+
+    /// Draws `query` in place of the query files and inline queries, until
+    /// it is emptied. A query that does not compile shows its error in the
+    /// footer and draws no captures.
+    fn edit_query(&mut self, query: String) {
+        self.edited_query = Some(query).filter(|query| !query.trim().is_empty());
+        self.reuse_rows = false;
+        self.reload_query();
+    }
+
+    #[doc(hidden)]
+    pub fn is_searching(&self) -> bool {
+        self.search.is_some()
+    }
+
+    // an empty query clears the search
+    fn search(&mut self, query: String) {
+        if query.is_empty() {
+            self.search = None;
+            return;
+        }
+        let matches = self.find_kind(&query);
+        if matches.is_empty() {
+            self.message = Some(format!("no nodes matching `{query}`"));
+        }
+        self.search = Some(Search {
+            query,
+            matched: matches.iter().copied().collect(),
+            matches,
+            current: 0,
+        });
+        self.jump_to_match(0);
+    }
+
+    /// Selects the first node whose kind contains `kind`, as a search for
+    /// it would, and scrolls to it. `false` when there is none.
+    #[doc(hidden)]
+    pub fn select_kind(&mut self, kind: &str) -> bool {
+        let Some(&key) = self.find_kind(kind).first() else {
+            return false;
+        };
+        self.jump_to(key);
+        true
+    }
+
+    fn find_kind(&self, query: &str) -> Vec<NodeKey> {
+        descendants(self.tree.root_node())
+            .into_iter()
+            .filter(|node| node.kind().contains(query))
+            .map(NodeKey::of)
+            .collect()
+    }
+
+    // recompute matches against a freshly parsed tree
+    fn refresh_search(&mut self) {
+        if let Some(query) = self.search.as_ref().map(|s| s.query.clone()) {
+            let matches = self.find_kind(&query);
+            let search = self.search.as_mut().unwrap();
+            search.current = search.current.min(matches.len().saturating_sub(1));
+            search.matched = matches.iter().copied().collect();
+            search.matches = matches;
+        }
+    }
+
+    // the first ERROR or MISSING node after the selection, wrapping around
+    #[doc(hidden)]
+    pub fn next_error(&mut self) {
+        let nodes = descendants(self.tree.root_node());
+        let start = self
+            .selected
+            .and_then(|key| nodes.iter().position(|n| NodeKey::of(*n) == key))
+            .map_or(0, |i| i + 1);
+        let next = nodes[start..]
+            .iter()
+            .chain(&nodes[..start])
+            .find(|n| n.is_error() || n.is_missing());
+        match next {
+            Some(node) => self.jump_to(NodeKey::of(*node)),
+            None => self.message = Some("no errors".to_owned()),
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn next_match(&mut self) {
+        self.jump_to_match(1);
+    }
+
+    #[doc(hidden)]
+    pub fn prev_match(&mut self) {
+        self.jump_to_match(-1);
+    }
+
+    fn jump_to_match(&mut self, delta: isize) {
+        let Some(search) = &mut self.search else {
+            return;
+        };
+        if search.matches.is_empty() {
+            return;
+        }
+        let len = search.matches.len() as isize;
+        search.current = (search.current as isize + delta).rem_euclid(len) as usize;
+        let key = search.matches[search.current];
+        self.message = Some(format!(
+            "match {}/{} for `{}`",
+            search.current + 1,
+            search.matches.len(),
+            search.query
+        ));
+        self.jump_to(key);
+    }
+
+    // select a node and center it, expanding any collapsed ancestors and
+    // opening the runs that it or they are hidden in
+    fn jump_to(&mut self, key: NodeKey) {
+        let node = key.find(self.tree.root_node());
+        for (i, node) in iter::successors(node, |node| node.parent()).enumerate() {
+            if i > 0 {
+                self.collapsed.remove(&NodeKey::of(node));
+            }
+            let head = run_head(node);
+            if head != node {
+                self.open_runs.insert(NodeKey::of(head));
+            }
+        }
+        self.selected = Some(key);
+        self.follow = Some(Follow::Center);
+        self.steps = OnceCell::new();
+    }
+
+    // `@` followed by a node kind and optionally its 1-based index among
+    // the nodes of that kind, an empty input unpins
+    fn pin(&mut self, input: &str) {
+        let mut words = input.split_whitespace();
+        let Some(kind) = words.next() else {
+            self.pin = None;
+            self.message = Some("unpinned".to_owned());
+            return;
+        };
+        let index = match words.next().map(str::parse::<usize>) {
+            None => 0,
+            Some(Ok(n)) if n > 0 => n - 1,
+            Some(_) => {
+                self.message = Some(format!(
+                    "expected a kind and a 1-based index, got `{input}`"
+                ));
+                return;
+            }
+        };
+        self.pin = Some((kind.to_owned(), index));
+        self.follow_pin();
+    }
+
+    // select the pinned node in the current tree, if it still exists
+    fn follow_pin(&mut self) {
+        let Some((kind, index)) = self.pin.clone() else {
+            return;
+        };
+        let target = descendants(self.tree.root_node())
+            .into_iter()
+            .filter(|node| node.kind() == kind)
+            .nth(index)
+            .map(NodeKey::of);
+        match target {
+            Some(key) => self.jump_to(key),
+            None => self.message = Some(format!("pinned: no {kind} #{} in the tree", index + 1)),
+        }
+    }
+
+    // `:` followed by a byte offset or a 1-based `line:column`
+    fn goto(&mut self, input: &str) {
+        let Some(offset) = parse_position(&self.src, input, self.config.tab_width) else {
+            self.message = Some(format!(
+                "expected a byte offset or line:column, got `{input}`"
+            ));
+            return;
+        };
+        let offset = if offset > self.src.len() {
+            self.message = Some(format!(
+                "{offset} is past the end, going to byte {}",
+                self.src.len()
+            ));
+            self.src.len()
+        } else {
+            offset
+        };
+        let root = self.tree.root_node();
+        let node = if self.config.toggles.named_only {
+            root.named_descendant_for_byte_range(offset, offset)
+        } else {
+            root.descendant_for_byte_range(offset, offset)
+        };
+        if let Some(node) = node {
+            self.jump_to(NodeKey::of(node));
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn toggle_focus(&mut self) {
+        self.focus = match self.focus {
+            Some(_) => None,
+            None => Some(NodeKey::of(self.selected_node())),
+        };
+        self.steps = OnceCell::new();
+    }
+
+    #[doc(hidden)]
+    pub fn toggle_collapse(&mut self) {
+        let Some(key) = self.selected else {
+            return;
+        };
+        // a collapsed run opens rather than hiding what is below its row
+        let node = self.selected_node();
+        let heads_run = run_head(node) == node && run_neighbor(node, true).is_some();
+        if self.config.toggles.collapse_repeats && heads_run && !self.open_runs.contains(&key) {
+            self.open_runs.insert(key);
+        } else if !self.collapsed.remove(&key) {
+            self.collapsed.insert(key);
+        }
+        self.steps = OnceCell::new();
+    }
+
+    #[doc(hidden)]
+    pub fn scroll_left(&mut self, columns: usize) {
+        self.reuse_rows = true;
+        self.hscroll = self.hscroll.saturating_sub(columns);
+    }
+
+    #[doc(hidden)]
+    pub fn scroll_right(&mut self, columns: usize) {
+        self.reuse_rows = true;
+        self.hscroll = self.hscroll.saturating_add(columns);
+    }
+
+    #[doc(hidden)]
+    pub fn page_down(&mut self) {
+        self.scroll_down(self.height);
+    }
+
+    #[doc(hidden)]
+    pub fn page_up(&mut self) {
+        self.scroll_up(self.height);
+    }
+
+    /// Indents each level by one more column.
+    pub fn increase_indent(&mut self) {
+        self.config.toggles.indent_level = self.config.toggles.indent_level.saturating_add(1);
+    }
+
+    /// Indents each level by one column less.
+    pub fn decrease_indent(&mut self) {
+        self.config.toggles.indent_level = self.config.toggles.indent_level.saturating_sub(1);
+    }
+
+    /// Draws one more level, lifting the limit past the deepest node.
+    pub fn increase_max_depth(&mut self) {
+        let deepest = tree_depth(&self.tree);
+        self.config.toggles.max_depth = self
+            .config
+            .toggles
+            .max_depth
+            .map(|max| max + 1)
+            .filter(|&max| max < deepest);
+        self.steps = OnceCell::new();
+    }
+
+    /// Draws one level less, starting from the deepest node.
+    pub fn decrease_max_depth(&mut self) {
+        let deepest = tree_depth(&self.tree);
+        self.config.toggles.max_depth = Some(
+            self.config
+                .toggles
+                .max_depth
+                .unwrap_or(deepest)
+                .min(deepest)
+                .saturating_sub(1),
+        );
+        self.steps = OnceCell::new();
+    }
+
+    #[doc(hidden)]
+    pub fn toggle_timings(&mut self) {
+        self.show_timings = !self.show_timings;
+    }
+
+    #[doc(hidden)]
+    pub fn toggle_help(&mut self) {
+        self.show_help = !self.show_help;
+    }
+
+    #[doc(hidden)]
+    pub fn toggle_subtree_export(&mut self) {
+        self.export_subtree = !self.export_subtree;
+    }
+
+    #[doc(hidden)]
+    pub fn toggle_stats(&mut self) {
+        self.toggle_view(View::Stats);
+    }
+
+    #[doc(hidden)]
+    pub fn toggle_sexp(&mut self) {
+        self.toggle_view(View::Sexp);
+    }
+
+    // there is nothing to list until a file has been skipped
+    #[doc(hidden)]
+    pub fn toggle_skipped(&mut self) {
+        if self.skipped.is_empty() {
+            self.view = View::Tree;
+        } else {
+            self.toggle_view(View::Skipped);
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn toggle_overlay(&mut self) {
+        self.toggle_view(View::Overlay);
+    }
+
+    // back to the tree when `view` is already shown
+    fn toggle_view(&mut self, view: View) {
+        self.view = if self.view == view { View::Tree } else { view };
+    }
+
+    /// Switches to the next [`GuideStyle`](crate::GuideStyle).
+    pub fn cycle_guides(&mut self) {
+        self.config.toggles.guide_style = self.config.toggles.guide_style.next();
+    }
+
+    /// Switches to the next [`RangeFormat`](crate::RangeFormat).
+    pub fn cycle_ranges(&mut self) {
+        self.config.toggles.show_ranges = self.config.toggles.show_ranges.next();
+    }
+
+    /// Shows or hides the source text of each node.
+    pub fn toggle_source(&mut self) {
+        self.config.toggles.show_src = !self.config.toggles.show_src;
+    }
+
+    /// Shows or hides anonymous nodes, parse errors are always shown.
+    pub fn toggle_named_only(&mut self) {
+        self.config.toggles.named_only = !self.config.toggles.named_only;
+        self.steps = OnceCell::new();
+    }
+
+    /// With a query, draws only the captured nodes and their ancestors, or
+    /// every node.
+    pub fn toggle_captures_only(&mut self) {
+        self.config.toggles.captures_only = !self.config.toggles.captures_only;
+        self.steps = OnceCell::new();
+    }
+
+    /// Shows the text of anonymous nodes even with the source text hidden.
+    pub fn toggle_anonymous_text(&mut self) {
+        self.config.toggles.show_anonymous_text = !self.config.toggles.show_anonymous_text;
+    }
+
+    /// Colors the guides of each level from the theme, or leaves them plain.
+    pub fn toggle_depth_guides(&mut self) {
+        self.config.toggles.depth_guides = !self.config.toggles.depth_guides;
+    }
+
+    /// Shows or hides the symbol id and flags after each kind.
+    pub fn toggle_node_flags(&mut self) {
+        self.config.toggles.show_node_flags = !self.config.toggles.show_node_flags;
+    }
+
+    /// Shows or hides the index of each node among its siblings.
+    pub fn toggle_sibling_index(&mut self) {
+        self.config.toggles.show_sibling_index = !self.config.toggles.show_sibling_index;
+    }
+
+    /// Shows or hides line numbers left of the tree.
+    pub fn toggle_gutter(&mut self) {
+        self.config.toggles.show_gutter = !self.config.toggles.show_gutter;
+    }
+
+    /// `path:line:column` of the selected node, for pasting into an editor.
+    fn location(&self) -> Option<String> {
+        let node = self.selected?.find(self.tree.root_node())?;
+        // editors count a tab as a single column
+        Some(format!(
+            "{}:{}:{}",
+            self.display_path(),
+            node.start_position().row + 1,
+            display_column(&self.src, node.start_byte(), 1) + 1
+        ))
+    }
+
+    /// `path:line:column: ERROR` or `MISSING kind` for each parse error in
+    /// the tree, in source order.
+    #[doc(hidden)]
+    pub fn problem_locations(&self) -> Vec<String> {
+        descendants(self.tree.root_node())
+            .into_iter()
+            .filter(|node| node.is_error() || node.is_missing())
+            .map(|node| {
+                let what = if node.is_missing() {
+                    format!("MISSING {}", node.kind())
+                } else {
+                    "ERROR".to_owned()
+                };
+                format!(
+                    "{}:{}:{}: {what}",
+                    self.display_path(),
+                    node.start_position().row + 1,
+                    display_column(&self.src, node.start_byte(), 1) + 1
+                )
+            })
+            .collect()
+    }
+
+    /// Copies the selected node's text, or shows it in the message line,
+    /// escaped onto one row, when the clipboard is unavailable.
+    #[doc(hidden)]
+    pub fn copy_selected(&mut self) {
+        let Some(node) = self
+            .selected
+            .and_then(|key| key.find(self.tree.root_node()))
+        else {
+            self.message = Some("no node selected".to_owned());
+            return;
+        };
+        let (text, _) = node_text(node.byte_range(), &self.src);
+        self.message = Some(match copy_to_clipboard(&text) {
+            Ok(()) => format!("copied {} bytes", text.len()),
+            // stderr would be drawn over by the next frame
+            Err(reason) => {
+                let width = self.width().saturating_sub(reason.chars().count() + 2);
+                format!("{reason}: {}", escape_truncated(&text, width))
+            }
+        });
+    }
+
+    /// Runs `template` from `[commands]` with `sh`, filled in from the
+    /// selected node, and shows how it exited. The command has the terminal
+    /// to itself until then.
+    #[doc(hidden)]
+    pub fn run_command(&mut self, template: &str) {
+        let Some(node) = self
+            .selected
+            .and_then(|key| key.find(self.tree.root_node()))
+        else {
+            self.message = Some("no node selected".to_owned());
+            return;
+        };
+        let (text, _) = node_text(node.byte_range(), &self.src);
+        let file = self.path.to_string_lossy();
+        let (start, end) = (node.start_byte().to_string(), node.end_byte().to_string());
+        let command = fill_command(
+            template,
+            &[
+                ("file", &*file),
+                ("start", start.as_str()),
+                ("end", end.as_str()),
+                ("text", &*text),
+            ],
+        );
+        verbose!("running {command}");
+        let status = process::Command::new("sh").arg("-c").arg(&command).status();
+        self.message = Some(match status {
+            Ok(status) => match status.code() {
+                Some(code) => format!("`{template}` exited with {code}"),
+                None => format!("`{template}` was killed by a signal"),
+            },
+            Err(e) => format!("unable to run `{template}`: {e}"),
+        });
+    }
+
+    #[doc(hidden)]
+    pub fn show_location(&mut self) {
+        let location = self.location();
+        let original = self.encoding.zip(self.selected).map(|(encoding, key)| {
+            format!(
+                ", byte {} of the {} file",
+                self.original_offset(key.start_byte),
+                encoding.name()
+            )
+        });
+        self.message = Some(match location {
+            Some(location) => location + &original.unwrap_or_default(),
+            None => "no node selected".to_owned(),
+        });
+    }
+
+    /// Shows or hides the line each node starts on.
+    pub fn toggle_source_line(&mut self) {
+        self.config.toggles.show_src_line = !self.config.toggles.show_src_line;
+    }
+
+    /// Wraps source text onto further rows, or truncates it.
+    pub fn toggle_wrap_source(&mut self) {
+        self.config.toggles.wrap_src = !self.config.toggles.wrap_src;
+    }
+
+    /// Shows or hides field names.
+    pub fn toggle_field_names(&mut self) {
+        self.config.toggles.show_field_name = !self.config.toggles.show_field_name;
+    }
+
+    /// Collapses runs of siblings of the same shape into one row, or expands
+    /// them.
+    pub fn toggle_collapse_repeats(&mut self) {
+        self.config.toggles.collapse_repeats = !self.config.toggles.collapse_repeats;
+        self.open_runs.clear();
+        self.steps = OnceCell::new();
+    }
+
+    /// Draws comments and other extras under the node they belong to, or in
+    /// place.
+    pub fn toggle_attach_extras(&mut self) {
+        self.config.toggles.attach_extras = !self.config.toggles.attach_extras;
+        self.steps = OnceCell::new();
+    }
+
+    /// The view state worth keeping for the next time this file is opened.
+    #[doc(hidden)]
+    pub fn session(&self) -> Session {
+        let save = |key: &NodeKey| (key.start_byte, key.end_byte, key.kind_id);
+        Session {
+            language: self.language_name.clone(),
+            scroll: self.scroll,
+            hscroll: self.hscroll,
+            selected: self.selected.as_ref().map(save),
+            collapsed: self.collapsed.iter().map(save).collect(),
+            // the skipped files are only known to this run
+            view: match self.view {
+                View::Skipped => View::Tree,
+                view => view,
+            },
+            show_help: self.show_help,
+            show_timings: self.show_timings,
+            toggles: self.config.toggles.clone(),
+        }
+    }
+
+    /// Applies a saved [`Session`]. Nodes are looked up in the current tree
+    /// and those no longer in it are dropped, as are all of them if the
+    /// session was saved with another grammar.
+    #[doc(hidden)]
+    pub fn restore(&mut self, session: Session) {
+        let root = self.tree.root_node();
+        let same_language = session.language == self.language_name;
+        let resolve = |&(start_byte, end_byte, kind_id): &SavedNode| {
+            let key = NodeKey {
+                start_byte,
+                end_byte,
+                kind_id,
+            };
+            (same_language && key.find(root).is_some()).then_some(key)
+        };
+        self.selected = session.selected.as_ref().and_then(resolve);
+        self.collapsed = session.collapsed.iter().filter_map(resolve).collect();
+        self.scroll = session.scroll;
+        self.hscroll = session.hscroll;
+        self.view = session.view;
+        self.show_help = session.show_help;
+        self.show_timings = session.show_timings;
+
+        self.config.toggles = session.toggles;
+        self.steps = OnceCell::new();
+        self.reuse_rows = false;
+    }
+
+    /// Why the last reload failed, while the previous tree is still shown.
+    pub(crate) fn status(&self) -> Option<&str> {
+        self.status.as_deref()
+    }
+
+    /// The whole tree as JSON, as `--export json` writes it.
+    pub(crate) fn json_tree(&self) -> serde_json::Value {
+        export::json_tree(self.tree.root_node(), &self.capture_map())
+    }
+
+    /// The smallest node at a byte offset or 1-based `line:column`, as
+    /// JSON. `None` when the position does not parse, one past the end is
+    /// the last node.
+    pub(crate) fn json_node_at(&self, position: &str) -> Option<serde_json::Value> {
+        let offset = parse_position(&self.src, position, self.config.tab_width)?;
+        let offset = offset.min(self.src.len());
+        let node = self
+            .tree
+            .root_node()
+            .descendant_for_byte_range(offset, offset)?;
+        Some(export::json_node(node, &self.capture_map()))
+    }
+
+    /// Whether the source was piped in, shown as `<stdin>` and never reloaded.
+    #[doc(hidden)]
+    pub fn is_stdin(&self) -> bool {
+        self.path == Path::new("-")
+    }
+
+    /// Whether the source was passed as [`INLINE`] code, never reloaded
+    /// either.
+    #[doc(hidden)]
+    pub fn is_inline(&self) -> bool {
+        self.path == Path::new(INLINE)
+    }
+
+    fn display_path(&self) -> String {
+        if self.is_stdin() {
+            "<stdin>".to_owned()
+        } else {
+            self.path.display().to_string()
+        }
+    }
+
+    #[doc(hidden)]
     pub fn reload(&mut self) {
-        let src = std::fs::read_to_string(&self.path).unwrap();
-        let new = Self::new(
-            src.as_bytes(),
-            &self.path,
-            self.query_path.as_ref(),
-            self.language.clone(),
+        // there is no file to read again, nor one to have been deleted
+        if self.is_stdin() || self.is_inline() {
+            return;
+        }
+        verbose!("reload started");
+        let start = Instant::now();
+        // keep showing the last good tree when the reload fails
+        self.status = self.try_reload().err().map(|e| e.to_string());
+        self.reload_query();
+        verbose!("reload finished in {:?}", start.elapsed());
+    }
+
+    fn try_reload(&mut self) -> Result<(), AppError> {
+        // the watch is on the directory, so the file coming back is seen
+        let read = || {
+            fs::read(&self.path).map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => AppError::Deleted {
+                    path: self.path.clone(),
+                },
+                _ => AppError::io(&self.path, e),
+            })
+        };
+        let mut src = read()?;
+        // an empty read is likely a save caught between truncate and write
+        if src.is_empty() && !self.src.is_empty() {
+            thread::sleep(Duration::from_millis(50));
+            src = read()?;
+        }
+        let original = match self.encoding {
+            Some(encoding) => {
+                let decoded = decode(&self.path, &src, encoding)?;
+                std::mem::replace(&mut src, decoded)
+            }
+            None => Vec::new(),
+        };
+        self.reparse(src)?;
+        self.original = original;
+        self.problems = count_problems(&self.tree);
+        self.refresh_search();
+        self.follow_pin();
+        Ok(())
+    }
+
+    /// Switches to the next grammar in the [`Registry`] and parses the
+    /// source from scratch. The query is recompiled for the new grammar.
+    #[doc(hidden)]
+    pub fn cycle_language(&mut self) {
+        // past a grammar that failed to load, rather than back to it
+        let after = match &self.grammar_error {
+            Some(AppError::UnsupportedLanguage { name, .. }) => name.clone(),
+            _ => self.language_name.clone(),
+        };
+        let Some((name, language)) = self.registry.next_after(&after) else {
+            return;
+        };
+        let name = name.to_owned();
+        match self.replace_source(&name, language, self.src.clone()) {
+            // the grammar already shown stays, the header says why
+            Err(e @ AppError::UnsupportedLanguage { .. }) => self.grammar_error = Some(e),
+            Err(e) => self.message = Some(e.to_string()),
+            Ok(()) => (),
+        }
+    }
+
+    /// Grammars to switch between, and to detect in directory mode.
+    /// Defaults to [`Registry::builtin`].
+    pub fn with_registry(mut self, registry: Registry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Browses the given files with [`App::next_file`] and
+    /// [`App::prev_file`], the first of them being the one already shown.
+    #[doc(hidden)]
+    pub fn with_files(mut self, files: Vec<PathBuf>) -> Self {
+        self.files = files;
+        self
+    }
+
+    #[doc(hidden)]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    #[doc(hidden)]
+    pub fn next_file(&mut self) {
+        self.switch_file(1);
+    }
+
+    #[doc(hidden)]
+    pub fn prev_file(&mut self) {
+        self.switch_file(-1);
+    }
+
+    // files that cannot be shown are taken out of the list and the next
+    // one in the same direction is tried, so one bad file does not end
+    // the session
+    fn switch_file(&mut self, delta: isize) {
+        while self.files.len() > 1 {
+            let len = self.files.len() as isize;
+            let index = (self.file_index as isize + delta).rem_euclid(len) as usize;
+            let path = self.files[index].clone();
+            match self.open_file(&path) {
+                Ok(()) => {
+                    self.file_index = index;
+                    self.scroll = 0;
+                    self.hscroll = 0;
+                    self.status = None;
+                    return;
+                }
+                Err(reason) => self.skip_file(index, reason),
+            }
+        }
+    }
+
+    /// Shows the first of the files given to [`App::with_files`] that can
+    /// be shown, taking those before it out of the list as paging to them
+    /// would. False when there is none.
+    #[doc(hidden)]
+    pub fn open_first_file(&mut self) -> bool {
+        while let Some(path) = self.files.first().cloned() {
+            match self.open_file(&path) {
+                Ok(()) => {
+                    self.file_index = 0;
+                    return true;
+                }
+                Err(reason) => self.skip_file(0, reason),
+            }
+        }
+        false
+    }
+
+    fn skip_file(&mut self, index: usize, reason: String) {
+        let path = self.files.remove(index);
+        verbose!("skipping {}: {reason}", path.display());
+        if index < self.file_index {
+            self.file_index -= 1;
+        }
+        self.skipped.push((path, reason));
+        self.message = Some(format!("skipped {} unreadable files", self.skipped.len()));
+    }
+
+    fn open_file(&mut self, path: &Path) -> Result<(), String> {
+        let mut src = fs::read(path).map_err(|e| e.to_string())?;
+        let original = match self.encoding {
+            Some(encoding) => {
+                let decoded = decode(path, &src, encoding)
+                    .map_err(|_| format!("not valid {}", encoding.name()))?;
+                std::mem::replace(&mut src, decoded)
+            }
+            None if std::str::from_utf8(&src).is_err() => {
+                return Err("not valid UTF-8".to_owned());
+            }
+            None => Vec::new(),
+        };
+        let (name, language) = self.registry.detect(path).map_or(
+            (self.language_name.clone(), self.language.clone()),
+            |(name, language)| (name.to_owned(), language),
         );
-        *self = Self {
-            config: self.config,
-            ..new
+        let previous = std::mem::replace(&mut self.path, path.to_owned());
+        self.replace_source(&name, language, src).map_err(|e| {
+            self.path = previous;
+            e.to_string()
+        })?;
+        self.original = original;
+        Ok(())
+    }
+
+    // parse new source from scratch, possibly with another grammar, keeping
+    // everything as it was if that fails
+    fn replace_source(
+        &mut self,
+        name: &str,
+        language: tree_sitter::Language,
+        src: Vec<u8>,
+    ) -> Result<(), AppError> {
+        if self.parser.set_language(&language).is_err() {
+            // the previous grammar is known to be compatible
+            self.parser.set_language(&self.language).unwrap();
+            return Err(AppError::UnsupportedLanguage {
+                name: name.to_owned(),
+                version: language.version(),
+            });
+        }
+        let start = Instant::now();
+        let tree = parse(&mut self.parser, &src, None, &self.path).inspect_err(|_| {
+            self.parser.set_language(&self.language).unwrap();
+        })?;
+        self.parse_time = start.elapsed();
+        verbose!("parsed {} as {name}", self.path.display());
+        self.language = language;
+        self.language_name = name.to_owned();
+        self.grammar_error = None;
+        self.tree = tree;
+        self.captures = OnceCell::new();
+        self.window_captures = OnceCell::new();
+        self.steps = OnceCell::new();
+        self.char_starts = OnceCell::new();
+        self.reuse_rows = false;
+        self.src = src;
+        self.problems = count_problems(&self.tree);
+        // nodes from the old tree mean nothing in the new one
+        self.selected = None;
+        self.focus = None;
+        self.collapsed.clear();
+        self.open_runs.clear();
+        self.changed.clear();
+        self.changed_draws = 0;
+        self.refresh_search();
+        self.follow_pin();
+        self.reload_query();
+        Ok(())
+    }
+
+    /// Re-reads only the queries, the tree is left as it is. A query that
+    /// fails to compile is dropped and its error shown in the footer, the
+    /// others are still drawn.
+    #[doc(hidden)]
+    pub fn reload_query(&mut self) {
+        let edited;
+        let sources = match &self.edited_query {
+            Some(query) => {
+                edited = [QuerySource::Inline(query.clone())];
+                &edited[..]
+            }
+            None => &self.query_sources[..],
+        };
+        if sources.is_empty() && self.queries.is_empty() {
+            return;
+        }
+        self.captures = OnceCell::new();
+        self.window_captures = OnceCell::new();
+        self.steps = OnceCell::new();
+        self.queries.clear();
+        self.query_error = None;
+        for result in load_queries(&self.language, sources) {
+            match result {
+                Ok(loaded) => self.queries.push(loaded),
+                Err(e) => {
+                    self.query_error.get_or_insert_with(|| e.to_string());
+                }
+            }
+        }
+    }
+
+    // describe the change as a single edit so that tree-sitter can reuse
+    // the unchanged parts of the old tree
+    fn reparse(&mut self, src: Vec<u8>) -> Result<(), AppError> {
+        let edit = edit_between(&self.src, &src);
+        let mut old_tree = self.tree.clone();
+        old_tree.edit(&edit);
+
+        let start = Instant::now();
+        let tree = parse(&mut self.parser, &src, Some(&old_tree), &self.path)?;
+        self.parse_time = start.elapsed();
+        if verbose_enabled() {
+            let incremental = self.parse_time;
+            let start = Instant::now();
+            let _ = parse(&mut self.parser, &src, None, &self.path);
+            let full = start.elapsed();
+            verbose!(
+                "edited {}..{} into {}..{}: incremental parse {incremental:?}, full parse {full:?} ({:.1}x)",
+                edit.start_byte,
+                edit.old_end_byte,
+                edit.start_byte,
+                edit.new_end_byte,
+                full.as_secs_f64() / incremental.as_secs_f64().max(f64::EPSILON)
+            );
+        }
+
+        self.diff_trees(&old_tree, &tree);
+        self.tree = tree;
+        self.captures = OnceCell::new();
+        self.window_captures = OnceCell::new();
+        self.steps = OnceCell::new();
+        self.char_starts = OnceCell::new();
+        self.reuse_rows = false;
+        self.src = src;
+        self.remap_keys(&edit);
+        Ok(())
+    }
+
+    // nodes count as unchanged when one of the same kind and extent was in
+    // the old tree, whose positions the edit has already shifted
+    fn diff_trees(&mut self, old_tree: &Tree, tree: &Tree) {
+        let keys = |tree: &Tree| -> HashSet<NodeKey> {
+            descendants(tree.root_node())
+                .into_iter()
+                .map(NodeKey::of)
+                .collect()
         };
+        let old = keys(old_tree);
+        let new = keys(tree);
+        self.changed = new.difference(&old).copied().collect();
+        self.diff = (self.changed.len(), old.difference(&new).count());
+        self.changed_draws = CHANGED_DRAWS;
+        verbose!(
+            "reload added {} nodes and removed {}",
+            self.diff.0,
+            self.diff.1
+        );
+    }
+
+    // carry the selection, focus and collapsed nodes over to the new tree,
+    // nodes after the edit have moved by the change in length. a focused
+    // node that is gone leaves the whole tree drawn
+    fn remap_keys(&mut self, edit: &InputEdit) {
+        let root = self.tree.root_node();
+        let exists = |key: &NodeKey| key.find(root).is_some();
+        if let Some(key) = self.selected {
+            let key = key.shifted(edit);
+            self.selected = Some(if exists(&key) { key } else { NodeKey::of(root) });
+        }
+        self.focus = self.focus.map(|key| key.shifted(edit)).filter(exists);
+        self.collapsed = self
+            .collapsed
+            .iter()
+            .map(|key| key.shifted(edit))
+            .filter(exists)
+            .collect();
+        self.open_runs = self
+            .open_runs
+            .iter()
+            .map(|key| key.shifted(edit))
+            .filter(exists)
+            .collect();
+    }
+}
+
+#[cfg(feature = "clipboard")]
+fn copy_to_clipboard(text: &str) -> Result<(), String> {
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.set_text(text))
+        .map_err(|e| format!("unable to copy: {e}"))
+}
+
+#[cfg(not(feature = "clipboard"))]
+fn copy_to_clipboard(_text: &str) -> Result<(), String> {
+    Err("built without the clipboard feature".to_owned())
+}
+
+// a parse that ran out of time would otherwise be resumed by the next one
+fn parse(
+    parser: &mut Parser,
+    src: &[u8],
+    old_tree: Option<&Tree>,
+    path: &Path,
+) -> Result<Tree, AppError> {
+    parser.parse(src, old_tree).ok_or_else(|| {
+        parser.reset();
+        AppError::ParseFailed {
+            path: path.to_owned(),
+        }
+    })
+}
+
+// the smallest single edit that turns old into new
+fn edit_between(old: &[u8], new: &[u8]) -> InputEdit {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_end_byte = old.len() - suffix;
+    let new_end_byte = new.len() - suffix;
+    InputEdit {
+        start_byte: prefix,
+        old_end_byte,
+        new_end_byte,
+        start_position: point_at(old, prefix),
+        old_end_position: point_at(old, old_end_byte),
+        new_end_position: point_at(new, new_end_byte),
+    }
+}
+
+// each query compiled in turn, with capture names namespaced by their
+// file once there are several
+fn load_queries<'a>(
+    language: &'a tree_sitter::Language,
+    sources: &'a [QuerySource],
+) -> impl Iterator<Item = Result<LoadedQuery, AppError>> + 'a {
+    let namespaced = sources.len() > 1;
+    sources.iter().map(move |source| {
+        let query = load_query(language, source)?;
+        let names = query
+            .capture_names()
+            .iter()
+            .map(|name| {
+                if namespaced {
+                    format!("{}:{name}", source.namespace())
+                } else {
+                    name.to_string()
+                }
+            })
+            .collect();
+        Ok(LoadedQuery { query, names })
+    })
+}
+
+fn load_query(language: &tree_sitter::Language, source: &QuerySource) -> Result<Query, AppError> {
+    verbose!("loading query {}", source.path().display());
+    let path = source.path();
+    let query_src = match source {
+        QuerySource::Path(path) => fs::read_to_string(path).map_err(|e| AppError::io(path, e))?,
+        QuerySource::Inline(query) => query.clone(),
+    };
+    let query = Query::new(language, &query_src).map_err(|e| AppError::QueryParse {
+        path: path.to_owned(),
+        offset: e.offset,
+        message: e.message,
+    })?;
+    verbose!(
+        "compiled query {}: {} patterns",
+        path.display(),
+        query.pattern_count()
+    );
+    Ok(query)
+}
+
+// predicates tree-sitter leaves to the application, which this viewer does
+// not evaluate, so their patterns may capture more than in an editor.
+// directives such as `#set!` do not filter and are left out
+fn ignored_predicates(queries: &[LoadedQuery]) -> Option<String> {
+    let mut operators: Vec<&str> = queries
+        .iter()
+        .flat_map(|loaded| {
+            let query = &loaded.query;
+            (0..query.pattern_count()).flat_map(move |i| query.general_predicates(i))
+        })
+        .map(|predicate| &*predicate.operator)
+        .filter(|operator| !operator.ends_with('!'))
+        .collect();
+    operators.sort_unstable();
+    operators.dedup();
+    (!operators.is_empty()).then(|| {
+        operators
+            .iter()
+            .map(|operator| format!("#{operator}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    })
+}
+
+fn point_at(src: &[u8], byte: usize) -> Point {
+    let before = &src[..byte];
+    let row = before.iter().filter(|&&b| b == b'\n').count();
+    let column = before.iter().rev().take_while(|&&b| b != b'\n').count();
+    Point::new(row, column)
+}
+
+fn node_count(tree: &Tree) -> usize {
+    descendants(tree.root_node()).len()
+}
+
+// `template` with each `{name}` of `values` replaced by its value, quoted
+// for the shell. other braces are left as they are
+fn fill_command(template: &str, values: &[(&str, &str)]) -> String {
+    let mut command = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        command.push_str(&rest[..open]);
+        rest = &rest[open + 1..];
+        let value = values.iter().find_map(|(name, value)| {
+            let after = rest.strip_prefix(name)?.strip_prefix('}')?;
+            Some((after, value))
+        });
+        match value {
+            Some((after, value)) => {
+                command.push('\'');
+                command.push_str(&value.replace('\'', r"'\''"));
+                command.push('\'');
+                rest = after;
+            }
+            None => command.push('{'),
+        }
+    }
+    command.push_str(rest);
+    command
+}
+
+// invalid UTF-8 (or a node splitting a character) is replaced with U+FFFD,
+// the flag says whether that happened
+fn node_text(bytes: std::ops::Range<usize>, src: &[u8]) -> (Cow<'_, str>, bool) {
+    let text = String::from_utf8_lossy(&src[bytes]);
+    let lossy = matches!(text, Cow::Owned(_));
+    (text, lossy)
+}
+
+// a byte offset, or a 1-based `line:column` counted in display columns,
+// lines and columns past the end are clamped to the end of the source or
+// line, a column inside a tab lands on the tab
+fn parse_position(src: &[u8], input: &str, tab_width: usize) -> Option<usize> {
+    let input = input.trim();
+    let Some((line, column)) = input.split_once(':') else {
+        return input.parse().ok();
+    };
+    let line: usize = line.parse().ok()?;
+    let column: usize = column.parse().ok()?;
+    let line_start = src
+        .split_inclusive(|&b| b == b'\n')
+        .take(line.saturating_sub(1))
+        .map(<[u8]>::len)
+        .sum::<usize>();
+    let rest = &src[line_start..];
+    let line_len = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+    // the `\r` of a CRLF line ending is not a column
+    let line_len = line_len - rest[..line_len].ends_with(b"\r") as usize;
+    let text = String::from_utf8_lossy(&rest[..line_len]);
+    let target = column.saturating_sub(1);
+    let mut width = 0;
+    let mut offset = text.len();
+    for (i, c) in text.char_indices() {
+        width = advance(width, c, tab_width);
+        if width > target {
+            offset = i;
+            break;
+        }
+    }
+    // lossy decoding may have changed byte lengths
+    Some(line_start + offset.min(line_len))
+}
+
+/// Decodes the contents of `path` from `encoding` to the UTF-8 that
+/// [`App::new`] parses. A byte order mark for `encoding` is dropped, and
+/// anything malformed in it is an error rather than replaced.
+pub fn decode(path: &Path, src: &[u8], encoding: &'static Encoding) -> Result<Vec<u8>, AppError> {
+    let bom = match Encoding::for_bom(src) {
+        Some((bom_encoding, len)) if bom_encoding == encoding => len,
+        _ => 0,
+    };
+    encoding
+        .decode_without_bom_handling_and_without_replacement(&src[bom..])
+        .map(|text| text.into_owned().into_bytes())
+        .ok_or_else(|| AppError::Decode {
+            path: path.to_owned(),
+            encoding: encoding.name(),
+        })
+}
+
+// tree-sitter columns count bytes, this counts characters with tabs
+// expanded to the next multiple of `tab_width`
+fn display_column(src: &[u8], byte: usize, tab_width: usize) -> usize {
+    let line_start = src[..byte]
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |i| i + 1);
+    String::from_utf8_lossy(&src[line_start..byte])
+        .chars()
+        .fold(0, |column, c| advance(column, c, tab_width))
+}
+
+// the column after `c`, drawn starting at `column`
+fn advance(column: usize, c: char, tab_width: usize) -> usize {
+    let tab_width = tab_width.max(1);
+    if c == '\t' {
+        (column / tab_width + 1) * tab_width
+    } else {
+        column + 1
+    }
+}
+
+// drop the first `columns` visible columns of a styled line, escape
+// sequences are all kept so the styling of what remains is unchanged
+fn skip_columns(line: &str, columns: usize) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut skipped = 0;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            out.push(c);
+            // CSI sequences end with a byte in `@`..=`~`
+            for c in chars.by_ref() {
+                out.push(c);
+                if c != '[' && ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        } else if skipped < columns {
+            skipped += console::measure_text_width(c.encode_utf8(&mut [0; 4]));
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+// `[id=42, named, extra]`: the grammar's symbol id and whichever flags
+// are set, for debugging a grammar
+fn node_flags(step: &Step) -> String {
+    let mut flags = vec![format!("id={}", step.key.kind_id)];
+    for (set, flag) in [
+        (step.named, "named"),
+        (step.extra, "extra"),
+        (step.has_changes, "changed"),
+    ] {
+        if set {
+            flags.push(flag.to_owned());
+        }
+    }
+    format!("[{}]", flags.join(", "))
+}
+
+// number the children of `parent` in order, the named ones only when
+// `named`
+fn index_children<'t>(parent: Node<'t>, named: bool, index: &mut HashMap<Node<'t>, usize>) {
+    let mut cursor = parent.walk();
+    let children = if named {
+        parent.named_children(&mut cursor).collect::<Vec<_>>()
+    } else {
+        parent.children(&mut cursor).collect()
+    };
+    for (i, child) in children.into_iter().enumerate() {
+        index.insert(child, i);
+    }
+}
+
+// the name of the field `child` is in, if any
+pub(crate) fn field_of(parent: Node, child: Node) -> Option<&'static str> {
+    let mut cursor = parent.walk();
+    if !cursor.goto_first_child() {
+        return None;
+    }
+    loop {
+        if cursor.node() == child {
+            return cursor.field_name();
+        }
+        if !cursor.goto_next_sibling() {
+            return None;
+        }
+    }
+}
+
+// break at spaces into lines at most `width` characters long, words that
+// are longer get a line of their own
+fn wrap_words(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split(' ') {
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    lines.push(line);
+    lines
+}
+
+// split into a chunk `first` characters long, then chunks of `rest`
+fn wrap_chars(text: &str, first: usize, rest: usize) -> Vec<String> {
+    let mut chars = text.chars().peekable();
+    let mut chunks = vec![chars.by_ref().take(first).collect::<String>()];
+    while chars.peek().is_some() {
+        chunks.push(chars.by_ref().take(rest).collect());
+    }
+    chunks
+}
+
+// escape newlines, carriage returns, tabs and quotes so the text stays on
+// one row and cannot move the cursor, and cut it down to `width`
+// characters
+fn escape_truncated(text: &str, width: usize) -> String {
+    let escaped: String = text.escape_debug().collect();
+    if escaped.chars().count() <= width {
+        return escaped;
+    }
+    let mut truncated: String = escaped.chars().take(width.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+fn count_problems(tree: &Tree) -> (usize, usize) {
+    descendants(tree.root_node())
+        .into_iter()
+        .fold((0, 0), |(errors, missing), node| {
+            (
+                errors + node.is_error() as usize,
+                missing + node.is_missing() as usize,
+            )
+        })
+}
+
+fn tree_depth(tree: &Tree) -> usize {
+    let mut cursor = tree.walk();
+    let mut depth = 0;
+    let mut deepest = 0;
+    loop {
+        if cursor.goto_first_child() {
+            depth += 1;
+        } else if !goto_next(&mut cursor, &mut depth) {
+            break;
+        }
+        deepest = deepest.max(depth as usize);
+    }
+    deepest
+}
+
+// root and everything below it, in pre-order
+fn descendants(root: Node) -> Vec<Node> {
+    let mut nodes = vec![root];
+    let mut cursor = root.walk();
+    let mut depth = 0;
+    while cursor.goto_first_child() || goto_next(&mut cursor, &mut depth) {
+        nodes.push(cursor.node());
+    }
+    nodes
+}
+
+// moves to the next node in pre-order that is not a descendant of the
+// current one, returns false once the walk is over
+fn goto_next(cursor: &mut TreeCursor, depth: &mut i32) -> bool {
+    while !cursor.goto_next_sibling() {
+        if !cursor.goto_parent() {
+            return false;
+        }
+        *depth -= 1;
+    }
+    true
+}
+
+// do a and b have the same kinds all the way down
+// the next (or previous) member of a run of siblings of the same shape as
+// `node`, past the separators between them, such as the commas of a list,
+// and any comments
+fn run_neighbor(node: Node, forward: bool) -> Option<Node> {
+    let step = |node: Node| {
+        if forward {
+            node.next_sibling()
+        } else {
+            node.prev_sibling()
+        }
+    };
+    let mut next = step(node);
+    if node.is_named() {
+        let between = |n: &Node| !n.is_named() || (n.is_extra() && !node.is_extra());
+        while let Some(sibling) = next.filter(between) {
+            next = step(sibling);
+        }
+    }
+    next.filter(|&next| same_shape(node, next))
+}
+
+// the member of the run `node` is in that is drawn for all of it
+fn run_head(node: Node) -> Node {
+    let mut head = node;
+    while let Some(previous) = run_neighbor(head, false) {
+        head = previous;
+    }
+    head
+}
+
+fn same_shape(a: Node, b: Node) -> bool {
+    a.kind_id() == b.kind_id()
+        && a.child_count() == b.child_count()
+        && (0..a.child_count()).all(|i| same_shape(a.child(i).unwrap(), b.child(i).unwrap()))
+}
+
+// heuristic: an extra (usually a comment) belongs to the first named,
+// non-extra node that follows it among its siblings
+fn owner_of_extra(node: Node) -> Option<Node> {
+    if !node.is_extra() {
+        return None;
     }
+    let mut next = node.next_sibling();
+    while let Some(sibling) = next {
+        if !sibling.is_extra() {
+            return sibling.is_named().then_some(sibling);
+        }
+        next = sibling.next_sibling();
+    }
+    None
+}
+
+// the extra furthest before `owner` of those attached to it, if any
+fn first_attached_extra(owner: Node) -> Option<Node> {
+    if !owner.is_named() || owner.is_extra() {
+        return None;
+    }
+    let mut first = None;
+    let mut prev = owner.prev_sibling();
+    while let Some(sibling) = prev.filter(Node::is_extra) {
+        first = Some(sibling);
+        prev = sibling.prev_sibling();
+    }
+    first
 }
 
 // does a encompass b
 fn contains(a: &Range, b: &Range) -> bool {
     a.start_byte <= b.start_byte && a.end_byte >= b.end_byte
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a Rust source drawn without colors at a fixed width
+    fn app(src: &str, query: Option<&str>) -> App {
+        console::set_colors_enabled(false);
+        let queries = query
+            .map(|query| vec![QuerySource::Inline(query.to_owned())])
+            .unwrap_or_default();
+        App::new(
+            src.as_bytes(),
+            "test.rs",
+            queries,
+            tree_sitter_rust::language(),
+            "rust",
+            Config::default(),
+        )
+        .unwrap()
+        .with_width(120)
+    }
+
+    #[test]
+    fn query_window_follows_scrolling() {
+        let mut src = format!(
+            "const S: &str = \"{}\";\n",
+            "x".repeat(WINDOWED_QUERY_BYTES)
+        );
+        for i in 0..200 {
+            src.push_str(&format!("fn f{i}() {{}}\n"));
+        }
+        let query = "(function_item name: (identifier) @name)";
+        let mut app = app(&src, Some(query));
+        app.height = 10;
+
+        app.open_query_window();
+        let rows = app.rows();
+        assert!(app.update_query_window(&rows));
+        // the first rows were laid out without querying the whole file
+        assert!(app.captures.get().is_none());
+
+        let rows = app.rows();
+        app.scroll = rows.len() - app.height;
+        assert!(app.update_query_window(&rows));
+        let window = app.query_window.clone().unwrap();
+        assert!(window.start > WINDOWED_QUERY_BYTES);
+
+        let full = self::app(&src, Some(query));
+        let visible = app.scroll..app.scroll + app.height;
+        let lines = |rows: Vec<(NodeKey, String)>| -> Vec<String> {
+            rows[visible.clone()]
+                .iter()
+                .map(|(_, line)| line.clone())
+                .collect()
+        };
+        let drawn = lines(app.rows());
+        assert_eq!(drawn, lines(full.rows()));
+        assert!(drawn.iter().any(|line| line.contains("@name")));
+    }
+
+    #[test]
+    fn filters_keep_parse_errors() {
+        let src = "fn a() {}\nfn f() { let x = 1 }\nfn g() {} }\n";
+        let plain = app(src, None).render_plain().join("\n");
+        assert!(plain.contains("ERROR") && plain.contains("MISSING"));
+        // MISSING nodes are zero-width, drawn as ‹empty›
+        let problems = |app: &App| {
+            let lines = app.render();
+            let has = |text: &str| lines.iter().any(|line| line.contains(text));
+            (has("ERROR"), has("‹empty›"))
+        };
+
+        let mut named_only = app(src, None);
+        named_only.config.toggles.named_only = true;
+        assert_eq!(problems(&named_only), (true, true));
+
+        let mut shallow = app(src, None);
+        shallow.config.toggles.max_depth = Some(1);
+        assert_eq!(problems(&shallow), (true, true));
+
+        let mut captures_only = app(src, Some("(function_item name: (identifier) @name)"));
+        captures_only.config.toggles.captures_only = true;
+        assert_eq!(problems(&captures_only), (true, true));
+
+        let ranged = app(src, None).with_range(0..9);
+        assert_eq!(problems(&ranged), (true, true));
+    }
+
+    #[test]
+    fn columns_expand_tabs() {
+        let src = b"fn f() {\n\tlet x = 1;\n}\n";
+        // `let` after the tab, and `x`
+        assert_eq!(display_column(src, 10, 4), 4);
+        assert_eq!(display_column(src, 14, 4), 8);
+        assert_eq!(display_column(src, 14, 8), 12);
+        assert_eq!(parse_position(src, "2:5", 4), Some(10));
+        assert_eq!(parse_position(src, "2:9", 4), Some(14));
+        // inside the tab
+        assert_eq!(parse_position(src, "2:3", 4), Some(9));
+    }
+
+    #[test]
+    fn columns_skip_carriage_returns() {
+        let src = "fn f() {\r\n    g();\r\n}\r\n";
+        // `g` on the second line
+        assert_eq!(display_column(src.as_bytes(), 14, 4), 4);
+        assert_eq!(parse_position(src.as_bytes(), "2:5", 4), Some(14));
+        // past the end of the line, before its `\r`
+        assert_eq!(parse_position(src.as_bytes(), "2:100", 4), Some(18));
+
+        let mut app = app(src, None);
+        app.config.toggles.show_ranges = RangeFormat::Points;
+        let lines = app.render();
+        assert!(lines
+            .iter()
+            .any(|line| line.contains("identifier  2:5..2:6")));
+        assert!(lines.iter().all(|line| !line.contains('\r')));
+    }
+
+    #[test]
+    fn missing_nodes_are_marked_empty() {
+        let app = app("fn f() { let x = 1 }\n", None);
+        let missing = descendants(app.tree.root_node())
+            .into_iter()
+            .find(|node| node.is_missing())
+            .expect("a MISSING node");
+        let key = NodeKey::of(missing);
+        let rows = app.rows();
+        let (_, row) = rows.iter().find(|(k, _)| *k == key).unwrap();
+        assert!(row.contains(&format!("at {}", missing.start_byte())));
+        assert!(row.contains("‹empty›"));
+        let sexp = export::compact_sexp(app.tree.root_node(), &app.config);
+        assert!(sexp.contains("(MISSING \";\")"), "{sexp}");
+    }
+
+    #[test]
+    fn commands_quote_what_they_are_filled_with() {
+        let values = [("file", "it's.rs"), ("start", "4")];
+        assert_eq!(
+            fill_command("grep {start} {file} {end} {}", &values),
+            r"grep '4' 'it'\''s.rs' {end} {}"
+        );
+    }
+
+    #[test]
+    fn selection_is_drawn_over_captures_and_errors() {
+        let mut app = app("fn f() {} }\n", Some("(ERROR) @broken"));
+        app.config.theme.selected = Style::new().reverse().force_styling(true);
+        app.config.theme.error = Style::new().red().force_styling(true);
+        let error = descendants(app.tree.root_node())
+            .into_iter()
+            .find(|node| node.is_error())
+            .map(NodeKey::of)
+            .expect("an ERROR node");
+        let row = |app: &App| {
+            let rows = app.rows();
+            rows.into_iter().find(|(key, _)| *key == error).unwrap().1
+        };
+
+        let unselected = row(&app);
+        assert!(unselected.contains("\x1b[31m"));
+        assert!(console::strip_ansi_codes(&unselected).contains("@broken"));
+
+        app.selected = Some(error);
+        let plain = console::strip_ansi_codes(&unselected);
+        assert_eq!(
+            row(&app),
+            app.config.theme.selected.apply_to(plain).to_string()
+        );
+    }
+
+    #[test]
+    fn runs_collapse_across_separators_and_open() {
+        let mut app = app("const A: [u8; 3] = [1, 2, 3];\n", None);
+        app.config.toggles.collapse_repeats = true;
+        let drawn = |app: &App, key: NodeKey| app.rows().iter().any(|(k, _)| *k == key);
+        let array = descendants(app.tree.root_node())
+            .into_iter()
+            .find(|node| node.kind() == "array_expression")
+            .unwrap();
+        let first = NodeKey::of(array.named_child(0).unwrap());
+        let second = NodeKey::of(array.named_child(1).unwrap());
+        assert!(app
+            .rows()
+            .iter()
+            .any(|(_, row)| row.contains("integer_literal ×3")));
+        assert!(!drawn(&app, second));
+
+        app.jump_to(second);
+        assert!(drawn(&app, second));
+
+        app.open_runs.clear();
+        app.selected = Some(first);
+        app.toggle_collapse();
+        assert!(drawn(&app, second));
+        assert!(!app.collapsed.contains(&first));
+    }
+
+    #[test]
+    fn focus_shows_ancestors_and_children() {
+        let mut app = app("fn a() { let x = 1; }\nfn b() {}\n", None);
+        let root = app.tree.root_node();
+        let a = root.named_child(0).unwrap();
+        app.selected = Some(NodeKey::of(a));
+        let kinds = |app: &App| -> Vec<&'static str> {
+            let root = app.tree.root_node();
+            app.rows()
+                .iter()
+                .map(|(key, _)| key.find(root).unwrap().kind())
+                .collect()
+        };
+        let all = kinds(&app);
+
+        app.toggle_focus();
+        assert_eq!(
+            kinds(&app),
+            [
+                "source_file",
+                "function_item",
+                "fn",
+                "identifier",
+                "parameters",
+                "block"
+            ]
+        );
+
+        app.toggle_focus();
+        assert_eq!(kinds(&app), all);
+    }
+
+    #[test]
+    fn inline_source_is_not_reloaded() {
+        let mut app = App::new(
+            b"fn f() {}\n",
+            INLINE,
+            Vec::new(),
+            tree_sitter_rust::language(),
+            "rust",
+            Config::default(),
+        )
+        .unwrap();
+        app.reload();
+        assert!(app.status().is_none());
+    }
+
+    #[test]
+    fn focus_follows_edits() {
+        let mut app = app("fn a() {}\nfn b() {}\n", None);
+        let b = app.tree.root_node().named_child(1).unwrap();
+        app.selected = Some(NodeKey::of(b));
+        app.toggle_focus();
+
+        app.reparse(b"fn aa() {}\nfn b() {}\n".to_vec()).unwrap();
+        let b = app.tree.root_node().named_child(1).unwrap();
+        assert!(app.focus == Some(NodeKey::of(b)));
+
+        app.reparse(b"fn aa() {}\n".to_vec()).unwrap();
+        assert!(app.focus.is_none());
+    }
+
+    #[test]
+    fn spacing_lays_out_the_same_walk() {
+        let src = "fn f() { let x = 1; }\n";
+        let mut cached = app(src, None);
+        let before = cached.render();
+        cached.increase_indent();
+        cached.cycle_guides();
+        assert!(cached.steps.get().is_some());
+
+        let mut fresh = app(src, None);
+        fresh.increase_indent();
+        fresh.cycle_guides();
+        assert_eq!(cached.render(), fresh.render());
+        assert_ne!(cached.render(), before);
+
+        cached.toggle_named_only();
+        assert!(cached.steps.get().is_none());
+        assert!(cached.render().len() < before.len());
+    }
+}
@@ -0,0 +1,176 @@
+use std::{env, path::PathBuf, str::FromStr};
+
+use tree_viz::export::Format;
+
+pub struct Args {
+    pub positional: Vec<String>,
+    pub config_dir: Option<PathBuf>,
+    pub export: Option<Format>,
+    pub output: Option<PathBuf>,
+    pub verbose: bool,
+    pub about: bool,
+    pub once: bool,
+    /// source passed with `--code`, in place of a file
+    pub code: Option<String>,
+    pub check_query: bool,
+    /// each `--query PATH`, where `-` is stdin
+    pub query: Vec<String>,
+    pub query_string: Option<String>,
+    pub fail_on_error: bool,
+    /// print the rendered view as html, in place of an export
+    pub html: bool,
+    pub stats_only: bool,
+    /// print the source with captures colored, in place of the tree
+    pub overlay: bool,
+    /// `--captures a,b`: the capture names drawn, without their `@`
+    pub captures: Option<Vec<String>>,
+    pub width: Option<usize>,
+    /// `--encoding LABEL`, such as `latin1` or `utf-16le`
+    pub encoding: Option<String>,
+    /// `--range START..END`, in bytes
+    pub range: Option<(usize, usize)>,
+    /// `--lines FIRST..LAST`, 1-based and inclusive
+    pub lines: Option<(usize, usize)>,
+    /// `--serve PORT`, answering requests in place of the viewer
+    pub serve: Option<u16>,
+    /// `--select-kind KIND`, the kind of the node selected on opening
+    pub select_kind: Option<String>,
+}
+
+impl Args {
+    /// The command line, or what is wrong with it as one line to print.
+    pub fn parse() -> Result<Self, String> {
+        let mut positional = Vec::new();
+        let mut config_dir = None;
+        let mut export = None;
+        let mut output = None;
+        let mut verbose = false;
+        let mut about = false;
+        let mut once = false;
+        let mut code = None;
+        let mut check_query = false;
+        let mut query = Vec::new();
+        let mut query_string = None;
+        let mut fail_on_error = false;
+        let mut html = false;
+        let mut stats_only = false;
+        let mut overlay = false;
+        let mut captures = None;
+        let mut width = None;
+        let mut encoding = None;
+        let mut range = None;
+        let mut lines = None;
+        let mut serve = None;
+        let mut select_kind = None;
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => {
+                    let dir = value(&mut args, "--config expects a directory")?;
+                    config_dir = Some(PathBuf::from(dir));
+                }
+                "--export" => {
+                    let format = value(&mut args, "--export expects a format")?;
+                    export = Some(format.parse()?);
+                }
+                "--json" => export = Some(Format::Json),
+                "--dot" => export = Some(Format::Dot),
+                "--html" => html = true,
+                "--output" => {
+                    let path = value(&mut args, "--output expects a path")?;
+                    output = Some(PathBuf::from(path));
+                }
+                "--verbose" => verbose = true,
+                "--about" => about = true,
+                "--once" | "--no-watch" => once = true,
+                "--check-query" => check_query = true,
+                "--stats-only" => stats_only = true,
+                "--overlay" => overlay = true,
+                "--width" => {
+                    width = Some(parsed(&mut args, "--width expects a number of columns")?);
+                }
+                "--encoding" => {
+                    encoding = Some(value(&mut args, "--encoding expects an encoding name")?)
+                }
+                "--serve" => serve = Some(parsed(&mut args, "--serve expects a port")?),
+                "--select-kind" => {
+                    select_kind = Some(value(&mut args, "--select-kind expects a node kind")?)
+                }
+                "--range" => {
+                    let expected = "--range expects START..END";
+                    let bounds = value(&mut args, expected)?;
+                    range = Some(parse_bounds(&bounds).ok_or(expected)?);
+                }
+                "--lines" => {
+                    let expected = "--lines expects FIRST..LAST";
+                    let bounds = value(&mut args, expected)?;
+                    lines = Some(parse_bounds(&bounds).ok_or(expected)?);
+                }
+                "--captures" => {
+                    let names = value(&mut args, "--captures expects capture names")?;
+                    captures = Some(
+                        names
+                            .split(',')
+                            .map(|name| name.trim().trim_start_matches('@').to_owned())
+                            .filter(|name| !name.is_empty())
+                            .collect(),
+                    );
+                }
+                "--fail-on-error" => fail_on_error = true,
+                "--query" => query.push(value(&mut args, "--query expects a path or -")?),
+                "--query-string" => {
+                    query_string = Some(value(&mut args, "--query-string expects a query")?)
+                }
+                "--code" | "-e" => code = Some(value(&mut args, "--code expects the source")?),
+                _ => positional.push(arg),
+            }
+        }
+
+        Ok(Self {
+            positional,
+            config_dir,
+            export,
+            output,
+            verbose,
+            about,
+            once,
+            code,
+            check_query,
+            query,
+            query_string,
+            fail_on_error,
+            html,
+            stats_only,
+            overlay,
+            captures,
+            width,
+            encoding,
+            range,
+            lines,
+            serve,
+            select_kind,
+        })
+    }
+}
+
+// the argument after a flag
+fn value(args: &mut impl Iterator<Item = String>, expected: &str) -> Result<String, String> {
+    args.next().ok_or_else(|| expected.to_owned())
+}
+
+// the argument after a flag, which has to parse as a `T`
+fn parsed<T: FromStr>(
+    args: &mut impl Iterator<Item = String>,
+    expected: &str,
+) -> Result<T, String> {
+    value(args, expected)?
+        .parse()
+        .map_err(|_| expected.to_owned())
+}
+
+// `12..40`
+fn parse_bounds(bounds: &str) -> Option<(usize, usize)> {
+    let (start, end) = bounds.split_once("..")?;
+    Some((start.trim().parse().ok()?, end.trim().parse().ok()?))
+}
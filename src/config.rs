@@ -1,11 +1,132 @@
-use std::default::Default;
+use std::{
+    default::Default,
+    env, fs, io,
+    path::{Path, PathBuf},
+};
 
-#[derive(Clone, Copy)]
+use serde::{Deserialize, Serialize};
+use tree_sitter::Node;
+
+use crate::{
+    keys::{Commands, Keybindings},
+    theme::Theme,
+};
+
+/// Display settings. `config.toml` sets their initial values, any key it
+/// leaves out keeps the built-in default.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
+    /// read from the top level of `config.toml`, like the other keys
+    #[serde(flatten)]
+    pub toggles: Toggles,
+    /// longest source text shown on a row, in characters
+    pub src_width: usize,
+    /// columns a tab advances to the next multiple of, in `line:column`
+    /// ranges and in `:` positions
+    pub tab_width: usize,
+    pub theme: Theme,
+    pub keybindings: Keybindings,
+    /// run with the selected node, on keys no action is bound to
+    pub commands: Commands,
+}
+
+/// The settings that keys change at runtime, saved with each session. The
+/// rest of the config always comes from `config.toml`.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Toggles {
+    /// columns per level of the tree
     pub indent_level: usize,
-    pub show_ranges: bool,
+    /// how each node's range is written, if at all
+    pub show_ranges: RangeFormat,
+    /// the source text of each node, `‹empty›` for zero-width ones
     pub show_src: bool,
+    /// the field a node is in, before its kind
     pub show_field_name: bool,
+    /// `[id=42, named, extra]` after each kind
+    pub show_node_flags: bool,
+    /// `[2/5]` after each kind: its 0-based position among its siblings
+    /// and how many there are, counting named ones only with `named_only`
+    pub show_sibling_index: bool,
+    /// comments and other extras drawn under the node they belong to
+    pub attach_extras: bool,
+    /// runs of siblings of the same shape drawn as one row
+    pub collapse_repeats: bool,
+    /// anonymous nodes left out, parse errors are still drawn
+    pub named_only: bool,
+    /// deepest level drawn, `None` is unlimited
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<usize>,
+    pub guide_style: GuideStyle,
+    /// color each level of the guides from `theme.depths`
+    pub depth_guides: bool,
+    /// wrap source text onto further rows instead of truncating it
+    pub wrap_src: bool,
+    /// the line a node starts on, before its source text
+    pub show_src_line: bool,
+    /// line number of each node's start, left of the tree
+    pub show_gutter: bool,
+    /// draw only captured nodes and their ancestors
+    pub captures_only: bool,
+    /// the text of anonymous nodes, dimmed, even with `show_src` off
+    pub show_anonymous_text: bool,
+}
+
+/// How each level of indentation is drawn.
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GuideStyle {
+    /// `|` at every level
+    Bars,
+    /// `├`, `└` and `│` connectors
+    Tree,
+    Dots,
+    Spaces,
+}
+
+impl GuideStyle {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Bars => Self::Tree,
+            Self::Tree => Self::Dots,
+            Self::Dots => Self::Spaces,
+            Self::Spaces => Self::Bars,
+        }
+    }
+}
+
+/// How the range of each node is written after its kind.
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RangeFormat {
+    Off,
+    /// byte offsets, as tree-sitter has them
+    Bytes,
+    /// offsets in characters from the start of the file
+    Chars,
+    /// 1-based `line:column` positions
+    Points,
+}
+
+impl RangeFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Bytes => "bytes",
+            Self::Chars => "chars",
+            Self::Points => "points",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Bytes,
+            Self::Bytes => Self::Chars,
+            Self::Chars => Self::Points,
+            Self::Points => Self::Off,
+        }
+    }
 }
 
 impl Default for Config {
@@ -16,11 +137,134 @@ impl Default for Config {
 
 impl Config {
     fn new() -> Self {
+        Self {
+            toggles: Toggles::default(),
+            src_width: 40,
+            tab_width: 4,
+            theme: Theme::default(),
+            keybindings: Keybindings::default(),
+            commands: Commands::default(),
+        }
+    }
+
+    /// Whether named-only mode leaves `node` out. Parse errors are always
+    /// shown, even the anonymous tokens tree-sitter reports as missing.
+    pub fn hides(&self, node: Node) -> bool {
+        self.toggles.named_only && !node.is_named() && !node.is_missing()
+    }
+
+    /// Reads `config.toml`, a missing file means the defaults and a
+    /// malformed one is reported and ignored. `theme = "name"` in it reads
+    /// the `[theme]` table from `themes/<name>.toml` instead.
+    pub fn load(dir: Option<&ConfigDir>) -> Self {
+        let Some(dir) = dir else {
+            return Self::default();
+        };
+        let path = dir.config_file();
+        let src = match fs::read_to_string(&path) {
+            Ok(src) => src,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                eprintln!("tree-viz: unable to read {}: {e}", path.display());
+                return Self::default();
+            }
+        };
+        let mut config = Self::parse(&src, dir).unwrap_or_else(|e| {
+            eprintln!("tree-viz: ignoring {}: {e}", path.display());
+            Self::default()
+        });
+        let rejected = config.commands.reject_bound(&config.keybindings);
+        for conflict in config.keybindings.conflicts().iter().chain(&rejected) {
+            eprintln!("tree-viz: {}: {conflict}", path.display());
+        }
+        config
+    }
+
+    // a theme file that cannot be read leaves the default theme, rather
+    // than the whole config
+    fn parse(src: &str, dir: &ConfigDir) -> Result<Self, String> {
+        let mut table: toml::Table = toml::from_str(src).map_err(|e| e.to_string())?;
+        let Some(toml::Value::String(name)) = table.get("theme") else {
+            // parsed again for error messages that point into `src`
+            return toml::from_str(src).map_err(|e| e.to_string());
+        };
+        let path = dir.theme(name);
+        let theme = fs::read_to_string(&path)
+            .map_err(|e| format!("unable to read {}: {e}", path.display()))
+            .and_then(|src| {
+                toml::from_str(&src).map_err(|e| format!("ignoring {}: {e}", path.display()))
+            });
+        match theme {
+            Ok(theme) => table.insert("theme".to_owned(), toml::Value::Table(theme)),
+            Err(e) => {
+                eprintln!("tree-viz: {e}");
+                table.remove("theme")
+            }
+        };
+        toml::Value::Table(table)
+            .try_into()
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl Default for Toggles {
+    fn default() -> Self {
         Self {
             indent_level: 2,
-            show_ranges: true,
+            show_ranges: RangeFormat::Bytes,
             show_src: true,
             show_field_name: true,
+            show_node_flags: false,
+            show_sibling_index: false,
+            attach_extras: false,
+            collapse_repeats: false,
+            named_only: false,
+            max_depth: None,
+            guide_style: GuideStyle::Bars,
+            depth_guides: false,
+            wrap_src: false,
+            show_src_line: false,
+            show_gutter: false,
+            captures_only: false,
+            show_anonymous_text: false,
         }
     }
 }
+
+/// Location of user configuration: `config.toml`, `themes/` and
+/// `queries/<lang>/`. Defaults to `$XDG_CONFIG_HOME/tree-viz`, falling
+/// back to `~/.config/tree-viz`, unless overridden with `--config DIR`.
+pub struct ConfigDir {
+    root: PathBuf,
+}
+
+impl ConfigDir {
+    pub fn resolve(explicit: Option<PathBuf>) -> Option<Self> {
+        let root = explicit.or_else(|| {
+            env::var_os("XDG_CONFIG_HOME")
+                .filter(|p| !p.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+                .map(|base| base.join("tree-viz"))
+        })?;
+        Some(Self { root })
+    }
+
+    pub fn config_file(&self) -> PathBuf {
+        self.root.join("config.toml")
+    }
+
+    pub fn theme(&self, name: &str) -> PathBuf {
+        self.root.join("themes").join(format!("{name}.toml"))
+    }
+
+    pub fn queries(&self, language: &str) -> PathBuf {
+        self.root.join("queries").join(language)
+    }
+
+    // the query used when none is passed on the command line
+    pub fn default_query(&self, language: &str) -> Option<PathBuf> {
+        let path = self.queries(language).join("highlights.scm");
+        path.is_file().then_some(path)
+    }
+}
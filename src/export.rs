@@ -0,0 +1,546 @@
+use std::{collections::HashMap, io, str::FromStr};
+
+use serde_json::{json, Value};
+use tree_sitter::{Node, Point, Tree, TreeCursor};
+
+use crate::{app::field_of, config::Config};
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Paths,
+    Sexp,
+    Json,
+    Dot,
+}
+
+impl Format {
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            Self::Paths => "paths",
+            Self::Sexp => "sexp",
+            Self::Json => "json",
+            Self::Dot => "dot",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "paths" => Ok(Self::Paths),
+            "sexp" => Ok(Self::Sexp),
+            "json" => Ok(Self::Json),
+            "dot" => Ok(Self::Dot),
+            _ => Err(format!("unknown export format: {s}")),
+        }
+    }
+}
+
+/// Exports the tree below `root`, the root node of the tree for all of it.
+/// Paths are relative to `root`.
+pub(crate) fn write(
+    format: Format,
+    root: Node,
+    src: &[u8],
+    config: &Config,
+    captures: &HashMap<Node, Vec<&str>>,
+    out: &mut dyn io::Write,
+) -> io::Result<()> {
+    match format {
+        Format::Paths => paths(&mut root.walk(), src, "", out),
+        Format::Sexp => {
+            sexp(&mut root.walk(), config, 0, out)?;
+            writeln!(out)
+        }
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut *out, &json(&mut root.walk(), captures))?;
+            writeln!(out)
+        }
+        Format::Dot => {
+            writeln!(out, "digraph tree {{")?;
+            writeln!(out, "  node [fontname=monospace];")?;
+            dot(&mut root.walk(), config, captures, &mut 0, out)?;
+            writeln!(out, "}}")
+        }
+    }
+}
+
+// one line per node: `<structural-path>\t<kind>\t<text-hash>`, where each
+// path segment is the field name of the child, or its index among its
+// siblings when it has none. a field holding several children, as the
+// names of `import a, b`, numbers them after the first, `name`, `name[1]`,
+// so no two nodes share a path
+fn paths(
+    cursor: &mut TreeCursor,
+    src: &[u8],
+    path: &str,
+    out: &mut dyn io::Write,
+) -> io::Result<()> {
+    let node = cursor.node();
+    writeln!(
+        out,
+        "{}\t{}\t{:016x}",
+        if path.is_empty() { "/" } else { path },
+        node.kind(),
+        fnv1a(&src[node.byte_range()])
+    )?;
+
+    if cursor.goto_first_child() {
+        let mut index = 0;
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+        loop {
+            let segment = match cursor.field_name() {
+                Some(field) => {
+                    let count = seen.entry(field).or_default();
+                    *count += 1;
+                    match *count {
+                        1 => field.to_owned(),
+                        n => format!("{field}[{}]", n - 1),
+                    }
+                }
+                None => index.to_string(),
+            };
+            paths(cursor, src, &format!("{path}/{segment}"), out)?;
+            index += 1;
+            if !cursor.goto_next_sibling() {
+                break;
+            }
+        }
+        cursor.goto_parent();
+    }
+    Ok(())
+}
+
+// indented s-expression in the style of tree-sitter's test corpus, honoring
+// the field name and named-only toggles
+fn sexp(
+    cursor: &mut TreeCursor,
+    config: &Config,
+    depth: usize,
+    out: &mut dyn io::Write,
+) -> io::Result<()> {
+    let node = cursor.node();
+    write!(out, "{}", " ".repeat(depth * config.toggles.indent_level))?;
+    if config.toggles.show_field_name {
+        if let Some(field) = cursor.field_name() {
+            write!(out, "{field}: ")?;
+        }
+    }
+
+    // a missing token is written as tree-sitter's test corpus does
+    if !node.is_named() && node.is_missing() {
+        return write!(out, "(MISSING {:?})", node.kind());
+    }
+    if !node.is_named() {
+        return write!(out, "{:?}", node.kind());
+    }
+    if node.is_missing() {
+        write!(out, "(MISSING {}", node.kind())?;
+    } else {
+        write!(out, "({}", node.kind())?;
+    }
+    if cursor.goto_first_child() {
+        loop {
+            if !config.hides(cursor.node()) {
+                writeln!(out)?;
+                sexp(cursor, config, depth + 1, out)?;
+            }
+            if !cursor.goto_next_sibling() {
+                break;
+            }
+        }
+        cursor.goto_parent();
+    }
+    write!(out, ")")
+}
+
+/// The tree on one line, as in the expected output of tree-sitter's test
+/// corpus. Unlike `Node::to_sexp` this keeps anonymous nodes, quoted,
+/// unless they are hidden.
+pub(crate) fn compact_sexp(node: Node, config: &Config) -> String {
+    let mut out = String::new();
+    compact(&mut node.walk(), config, &mut out);
+    out
+}
+
+fn compact(cursor: &mut TreeCursor, config: &Config, out: &mut String) {
+    let node = cursor.node();
+    if config.toggles.show_field_name {
+        if let Some(field) = cursor.field_name() {
+            out.push_str(field);
+            out.push_str(": ");
+        }
+    }
+    if !node.is_named() {
+        if node.is_missing() {
+            out.push_str(&format!("(MISSING {:?})", node.kind()));
+        } else {
+            out.push_str(&format!("{:?}", node.kind()));
+        }
+        return;
+    }
+    out.push('(');
+    if node.is_missing() {
+        out.push_str("MISSING ");
+    }
+    out.push_str(node.kind());
+    if cursor.goto_first_child() {
+        loop {
+            if !config.hides(cursor.node()) {
+                out.push(' ');
+                compact(cursor, config, out);
+            }
+            if !cursor.goto_next_sibling() {
+                break;
+            }
+        }
+        cursor.goto_parent();
+    }
+    out.push(')');
+}
+
+/// One line per node, two spaces of indentation per level: the field
+/// name, the kind, quoted for anonymous nodes, the byte range, the
+/// 0-based `[row, column]` points and the captures. Every node is listed
+/// and nothing depends on the config, the terminal or the colors, so the
+/// lines can be compared against a checked-in snapshot.
+pub(crate) fn plain(tree: &Tree, captures: &HashMap<Node, Vec<&str>>) -> Vec<String> {
+    let mut lines = Vec::new();
+    plain_lines(&mut tree.walk(), captures, 0, &mut lines);
+    lines
+}
+
+fn plain_lines(
+    cursor: &mut TreeCursor,
+    captures: &HashMap<Node, Vec<&str>>,
+    depth: usize,
+    lines: &mut Vec<String>,
+) {
+    let node = cursor.node();
+    let mut line = "  ".repeat(depth);
+    if let Some(field) = cursor.field_name() {
+        line.push_str(field);
+        line.push_str(": ");
+    }
+    if node.is_missing() {
+        line.push_str("MISSING ");
+    }
+    if node.is_named() {
+        line.push_str(node.kind());
+    } else {
+        line.push_str(&format!("{:?}", node.kind()));
+    }
+    let (start, end) = (node.start_position(), node.end_position());
+    line.push_str(&format!(
+        " {}..{} [{}, {}] - [{}, {}]",
+        node.start_byte(),
+        node.end_byte(),
+        start.row,
+        start.column,
+        end.row,
+        end.column
+    ));
+    for name in captures.get(&node).into_iter().flatten() {
+        line.push_str(" @");
+        line.push_str(name);
+    }
+    lines.push(line);
+
+    if cursor.goto_first_child() {
+        loop {
+            plain_lines(cursor, captures, depth + 1, lines);
+            if !cursor.goto_next_sibling() {
+                break;
+            }
+        }
+        cursor.goto_parent();
+    }
+}
+
+/// The tree below `root` as the `json` export writes it.
+pub(crate) fn json_tree(root: Node, captures: &HashMap<Node, Vec<&str>>) -> Value {
+    json(&mut root.walk(), captures)
+}
+
+/// One node as in the `json` export but without its children, and with
+/// the kinds from the root down to it in `path`.
+pub(crate) fn json_node(node: Node, captures: &HashMap<Node, Vec<&str>>) -> Value {
+    let mut path = vec![node.kind()];
+    let mut ancestor = node.parent();
+    while let Some(parent) = ancestor {
+        path.push(parent.kind());
+        ancestor = parent.parent();
+    }
+    path.reverse();
+    json!({
+        "kind": node.kind(),
+        "named": node.is_named(),
+        "field_name": node.parent().and_then(|parent| field_of(parent, node)),
+        "start_byte": node.start_byte(),
+        "end_byte": node.end_byte(),
+        "start_point": point(node.start_position()),
+        "end_point": point(node.end_position()),
+        "captures": captures.get(&node).cloned().unwrap_or_default(),
+        "path": path,
+    })
+}
+
+fn json(cursor: &mut TreeCursor, captures: &HashMap<Node, Vec<&str>>) -> Value {
+    let node = cursor.node();
+    let field_name = cursor.field_name();
+
+    let mut children = Vec::new();
+    if cursor.goto_first_child() {
+        loop {
+            children.push(json(cursor, captures));
+            if !cursor.goto_next_sibling() {
+                break;
+            }
+        }
+        cursor.goto_parent();
+    }
+
+    json!({
+        "kind": node.kind(),
+        "named": node.is_named(),
+        "field_name": field_name,
+        "start_byte": node.start_byte(),
+        "end_byte": node.end_byte(),
+        "start_point": point(node.start_position()),
+        "end_point": point(node.end_position()),
+        "captures": captures.get(&node).cloned().unwrap_or_default(),
+        "children": children,
+    })
+}
+
+// graphviz digraph with one vertex per node, anonymous nodes drawn without
+// a box and captured nodes filled, returns the id given to the node
+fn dot(
+    cursor: &mut TreeCursor,
+    config: &Config,
+    captures: &HashMap<Node, Vec<&str>>,
+    next_id: &mut usize,
+    out: &mut dyn io::Write,
+) -> io::Result<usize> {
+    let node = cursor.node();
+    let id = *next_id;
+    *next_id += 1;
+
+    let mut attributes = vec![format!("label={:?}", node.kind())];
+    if !node.is_named() {
+        attributes.push("shape=plaintext".to_owned());
+        attributes.push("fontcolor=gray40".to_owned());
+    } else {
+        attributes.push("shape=box".to_owned());
+    }
+    if node.is_error() || node.is_missing() {
+        attributes.push("color=red".to_owned());
+    }
+    if let Some(names) = captures.get(&node) {
+        attributes.push("style=filled".to_owned());
+        attributes.push(format!("fillcolor={}", dot_color(names[0])));
+    }
+    writeln!(out, "  n{id} [{}];", attributes.join(", "))?;
+
+    if cursor.goto_first_child() {
+        loop {
+            if !config.hides(cursor.node()) {
+                let field = cursor.field_name();
+                let child = dot(cursor, config, captures, next_id, out)?;
+                match field.filter(|_| config.toggles.show_field_name) {
+                    Some(field) => writeln!(out, "  n{id} -> n{child} [label={field:?}];")?,
+                    None => writeln!(out, "  n{id} -> n{child};")?,
+                }
+            }
+            if !cursor.goto_next_sibling() {
+                break;
+            }
+        }
+        cursor.goto_parent();
+    }
+    Ok(id)
+}
+
+// x11 names close to the terminal palette captures are drawn with
+fn dot_color(capture: &str) -> &'static str {
+    const PALETTE: [&str; 8] = [
+        "gold",
+        "palegreen",
+        "lightcyan",
+        "lightblue",
+        "plum",
+        "orange",
+        "mediumpurple",
+        "darkseagreen",
+    ];
+    PALETTE[(fnv1a(capture.as_bytes()) % PALETTE.len() as u64) as usize]
+}
+
+/// Rendered rows, as from `App::render`, as a `<pre>` block in which the
+/// ANSI styling of each row becomes inline CSS.
+#[doc(hidden)]
+pub fn html(lines: &[String], out: &mut dyn io::Write) -> io::Result<()> {
+    writeln!(
+        out,
+        "<pre style=\"font-family: monospace; background: #1d1f21; color: #c5c8c6\">"
+    )?;
+    for line in lines {
+        writeln!(out, "{}", html_line(line))?;
+    }
+    writeln!(out, "</pre>")
+}
+
+// the SGR attributes in effect at some point of a row
+#[derive(Clone, Default, PartialEq)]
+struct Sgr {
+    fg: Option<u8>,
+    bg: Option<u8>,
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+    reverse: bool,
+}
+
+impl Sgr {
+    // applies the `;` separated parameters of one `\x1b[...m` sequence
+    fn apply(&mut self, params: &str) {
+        let mut params = params.split(';').map(|p| p.parse::<u8>().unwrap_or(0));
+        while let Some(param) = params.next() {
+            match param {
+                0 => *self = Self::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                7 => self.reverse = true,
+                30..=37 => self.fg = Some(param - 30),
+                40..=47 => self.bg = Some(param - 40),
+                90..=97 => self.fg = Some(param - 90 + 8),
+                100..=107 => self.bg = Some(param - 100 + 8),
+                38 | 48 => {
+                    // only the 256-color form, `38;5;n`, is ever emitted
+                    let color = params.nth(1);
+                    if param == 38 {
+                        self.fg = color;
+                    } else {
+                        self.bg = color;
+                    }
+                }
+                39 => self.fg = None,
+                49 => self.bg = None,
+                _ => (),
+            }
+        }
+    }
+
+    fn css(&self) -> String {
+        let (fg, bg) = if self.reverse {
+            (self.bg.or(Some(0)), self.fg.or(Some(7)))
+        } else {
+            (self.fg, self.bg)
+        };
+        let mut css = Vec::new();
+        if let Some(fg) = fg {
+            css.push(format!("color: {}", css_color(fg)));
+        }
+        if let Some(bg) = bg {
+            css.push(format!("background: {}", css_color(bg)));
+        }
+        if self.bold {
+            css.push("font-weight: bold".to_owned());
+        }
+        if self.dim {
+            css.push("opacity: 0.6".to_owned());
+        }
+        if self.italic {
+            css.push("font-style: italic".to_owned());
+        }
+        if self.underline {
+            css.push("text-decoration: underline".to_owned());
+        }
+        css.join("; ")
+    }
+}
+
+fn html_line(line: &str) -> String {
+    let mut out = String::new();
+    let mut sgr = Sgr::default();
+    let mut text = String::new();
+    let flush = |out: &mut String, text: &mut String, sgr: &Sgr| {
+        if text.is_empty() {
+            return;
+        }
+        if *sgr == Sgr::default() {
+            out.push_str(text);
+        } else {
+            out.push_str(&format!("<span style=\"{}\">{text}</span>", sgr.css()));
+        }
+        text.clear();
+    };
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => {
+                let mut sequence = String::new();
+                for c in chars.by_ref() {
+                    if c != '[' && ('@'..='~').contains(&c) {
+                        if c == 'm' {
+                            flush(&mut out, &mut text, &sgr);
+                            sgr.apply(&sequence);
+                        }
+                        break;
+                    }
+                    if c != '[' {
+                        sequence.push(c);
+                    }
+                }
+            }
+            '<' => text.push_str("&lt;"),
+            '>' => text.push_str("&gt;"),
+            '&' => text.push_str("&amp;"),
+            '"' => text.push_str("&quot;"),
+            c => text.push(c),
+        }
+    }
+    flush(&mut out, &mut text, &sgr);
+    out
+}
+
+// the xterm palette
+fn css_color(color: u8) -> String {
+    const BASIC: [&str; 16] = [
+        "#000000", "#cd0000", "#00cd00", "#cdcd00", "#0000ee", "#cd00cd", "#00cdcd", "#e5e5e5",
+        "#7f7f7f", "#ff0000", "#00ff00", "#ffff00", "#5c5cff", "#ff00ff", "#00ffff", "#ffffff",
+    ];
+    match color {
+        0..=15 => BASIC[color as usize].to_owned(),
+        16..=231 => {
+            let level = |c: u8| if c == 0 { 0 } else { 55 + c * 40 };
+            let c = color - 16;
+            format!(
+                "#{:02x}{:02x}{:02x}",
+                level(c / 36),
+                level(c / 6 % 6),
+                level(c % 6)
+            )
+        }
+        _ => {
+            let gray = 8 + (color - 232) * 10;
+            format!("#{gray:02x}{gray:02x}{gray:02x}")
+        }
+    }
+}
+
+fn point(point: Point) -> Value {
+    json!({ "row": point.row, "column": point.column })
+}
+
+// stable across runs and toolchains, unlike `DefaultHasher`
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
@@ -0,0 +1,335 @@
+use std::{collections::HashMap, fmt};
+
+use console::Key;
+use serde::{de, Deserialize, Deserializer};
+
+/// Everything a key can do in the viewer.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Action {
+    Quit,
+    Search,
+    EditQuery,
+    Goto,
+    Pin,
+    /// the key has to be pressed twice, as in vim's `gg`
+    SelectRoot,
+    SelectLast,
+    SelectParent,
+    SelectFirstChild,
+    SelectNextSibling,
+    SelectPrevSibling,
+    SelectNext,
+    SelectPrev,
+    /// the next search match, or the next range format when not searching
+    NextMatch,
+    PrevMatch,
+    NextError,
+    ToggleStats,
+    ToggleSexp,
+    ToggleOverlay,
+    ToggleHelp,
+    ToggleTimings,
+    CycleLanguage,
+    IncreaseIndent,
+    DecreaseIndent,
+    CycleRanges,
+    CycleGuides,
+    ToggleDepthGuides,
+    IncreaseMaxDepth,
+    DecreaseMaxDepth,
+    ToggleSource,
+    ToggleWrapSource,
+    ToggleSourceLine,
+    ToggleGutter,
+    ShowLocation,
+    CopySelected,
+    ToggleFieldNames,
+    ToggleNodeFlags,
+    ToggleSiblingIndex,
+    ToggleNamedOnly,
+    ToggleAttachExtras,
+    ToggleCollapseRepeats,
+    ToggleCapturesOnly,
+    CycleShownCaptures,
+    ToggleAnonymousText,
+    ToggleCollapse,
+    ToggleFocus,
+    Reload,
+    ExportSexp,
+    ExportJson,
+    ExportDot,
+    ToggleSubtreeExport,
+    ScrollDown,
+    ScrollUp,
+    ScrollLeft,
+    ScrollRight,
+    PageDown,
+    PageUp,
+    NextFile,
+    PrevFile,
+    ToggleSkipped,
+}
+
+// names in `[keybindings]`, and the keys bound by default
+const ACTIONS: &[(&str, Action, &[&str])] = &[
+    ("quit", Action::Quit, &["q", "esc"]),
+    ("search", Action::Search, &["/"]),
+    ("edit_query", Action::EditQuery, &["Q"]),
+    ("goto", Action::Goto, &[":"]),
+    ("pin", Action::Pin, &["P"]),
+    ("select_root", Action::SelectRoot, &["g"]),
+    ("select_last", Action::SelectLast, &["G"]),
+    ("select_parent", Action::SelectParent, &["u"]),
+    ("select_first_child", Action::SelectFirstChild, &["d"]),
+    ("select_next_sibling", Action::SelectNextSibling, &["}"]),
+    ("select_prev_sibling", Action::SelectPrevSibling, &["{"]),
+    ("select_next", Action::SelectNext, &["down"]),
+    ("select_prev", Action::SelectPrev, &["up"]),
+    ("next_match", Action::NextMatch, &["n"]),
+    ("prev_match", Action::PrevMatch, &["N"]),
+    ("next_error", Action::NextError, &["E"]),
+    ("toggle_stats", Action::ToggleStats, &["i"]),
+    ("toggle_sexp", Action::ToggleSexp, &["S"]),
+    ("toggle_overlay", Action::ToggleOverlay, &["O"]),
+    ("toggle_help", Action::ToggleHelp, &["?"]),
+    ("toggle_timings", Action::ToggleTimings, &["t"]),
+    ("cycle_language", Action::CycleLanguage, &["L"]),
+    ("increase_indent", Action::IncreaseIndent, &[">"]),
+    ("decrease_indent", Action::DecreaseIndent, &["<"]),
+    ("cycle_ranges", Action::CycleRanges, &[]),
+    ("cycle_guides", Action::CycleGuides, &["T"]),
+    ("toggle_depth_guides", Action::ToggleDepthGuides, &["R"]),
+    ("increase_max_depth", Action::IncreaseMaxDepth, &["]"]),
+    ("decrease_max_depth", Action::DecreaseMaxDepth, &["["]),
+    ("toggle_source", Action::ToggleSource, &["s"]),
+    ("toggle_wrap_source", Action::ToggleWrapSource, &["w"]),
+    ("toggle_source_line", Action::ToggleSourceLine, &["|"]),
+    ("toggle_gutter", Action::ToggleGutter, &["#"]),
+    ("show_location", Action::ShowLocation, &["p"]),
+    ("copy_selected", Action::CopySelected, &["y"]),
+    ("toggle_field_names", Action::ToggleFieldNames, &["f"]),
+    ("toggle_node_flags", Action::ToggleNodeFlags, &["I"]),
+    ("toggle_sibling_index", Action::ToggleSiblingIndex, &["b"]),
+    ("toggle_named_only", Action::ToggleNamedOnly, &["a"]),
+    ("toggle_attach_extras", Action::ToggleAttachExtras, &["c"]),
+    (
+        "toggle_collapse_repeats",
+        Action::ToggleCollapseRepeats,
+        &["x"],
+    ),
+    ("toggle_captures_only", Action::ToggleCapturesOnly, &["m"]),
+    ("cycle_shown_captures", Action::CycleShownCaptures, &["C"]),
+    ("toggle_anonymous_text", Action::ToggleAnonymousText, &["A"]),
+    ("toggle_collapse", Action::ToggleCollapse, &["space", "tab"]),
+    ("toggle_focus", Action::ToggleFocus, &["F"]),
+    ("reload", Action::Reload, &["r"]),
+    ("export_sexp", Action::ExportSexp, &["e"]),
+    ("export_json", Action::ExportJson, &["J"]),
+    ("export_dot", Action::ExportDot, &["D"]),
+    ("toggle_subtree_export", Action::ToggleSubtreeExport, &["X"]),
+    ("scroll_down", Action::ScrollDown, &["j"]),
+    ("scroll_up", Action::ScrollUp, &["k"]),
+    ("scroll_left", Action::ScrollLeft, &["h"]),
+    ("scroll_right", Action::ScrollRight, &["l"]),
+    ("page_down", Action::PageDown, &["pagedown"]),
+    ("page_up", Action::PageUp, &["pageup"]),
+    ("next_file", Action::NextFile, &["."]),
+    ("prev_file", Action::PrevFile, &[","]),
+    ("toggle_skipped", Action::ToggleSkipped, &["!"]),
+];
+
+// keys that are not a single character, as written in `[keybindings]`
+const NAMED_KEYS: &[(&str, Key)] = &[
+    ("up", Key::ArrowUp),
+    ("down", Key::ArrowDown),
+    ("left", Key::ArrowLeft),
+    ("right", Key::ArrowRight),
+    ("pageup", Key::PageUp),
+    ("pagedown", Key::PageDown),
+    ("home", Key::Home),
+    ("end", Key::End),
+    ("space", Key::Char(' ')),
+    ("tab", Key::Tab),
+    ("enter", Key::Enter),
+    ("esc", Key::Escape),
+    ("backspace", Key::Backspace),
+    ("delete", Key::Del),
+];
+
+fn parse_key(name: &str) -> Option<Key> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(Key::Char(c)),
+        _ => NAMED_KEYS
+            .iter()
+            .find(|(key_name, _)| *key_name == name)
+            .map(|(_, key)| key.clone()),
+    }
+}
+
+fn key_name(key: &Key) -> String {
+    match NAMED_KEYS.iter().find(|(_, named)| named == key) {
+        Some((name, _)) => (*name).to_owned(),
+        None => match key {
+            Key::Char(c) => c.to_string(),
+            key => format!("{key:?}"),
+        },
+    }
+}
+
+/// Which key does what. In `config.toml` a `[keybindings]` table maps
+/// action names to a key or a list of keys, such as `scroll_down = "j"`
+/// or `toggle_collapse = ["space", "enter"]`. Keys are single characters
+/// or one of `up`, `down`, `left`, `right`, `pageup`, `pagedown`, `home`,
+/// `end`, `space`, `tab`, `enter`, `esc`, `backspace` and `delete`. An
+/// action that is bound loses its default keys.
+#[derive(Clone)]
+pub struct Keybindings {
+    map: HashMap<Key, Action>,
+    // keys bound to more than one action, reported once at startup
+    conflicts: Vec<String>,
+}
+
+impl Default for Keybindings {
+    fn default() -> Self {
+        let mut map = HashMap::new();
+        for &(_, action, keys) in ACTIONS {
+            for key in keys {
+                map.insert(parse_key(key).unwrap(), action);
+            }
+        }
+        Self {
+            map,
+            conflicts: Vec::new(),
+        }
+    }
+}
+
+impl Keybindings {
+    pub fn action(&self, key: &Key) -> Option<Action> {
+        self.map.get(key).copied()
+    }
+
+    /// The keys bound to `action`, as they are written in the config.
+    pub fn keys_for(&self, action: Action) -> Vec<String> {
+        let mut keys: Vec<_> = self
+            .map
+            .iter()
+            .filter(|(_, bound)| **bound == action)
+            .map(|(key, _)| key_name(key))
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    /// Descriptions of keys bound to two actions, the last binding wins.
+    pub fn conflicts(&self) -> &[String] {
+        &self.conflicts
+    }
+
+    fn bind(&mut self, action: Action, keys: &[Key]) {
+        self.map.retain(|_, bound| *bound != action);
+        for key in keys {
+            if let Some(previous) = self.map.insert(key.clone(), action) {
+                if previous != action {
+                    self.conflicts.push(format!(
+                        "`{}` is bound to both {previous} and {action}, using {action}",
+                        key_name(key)
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Shell commands run with the selected node, from a `[commands]` table in
+/// `config.toml` mapping a key to a command, such as
+/// `K = "open https://docs.rs/releases/search?query={text}"`. Keys are
+/// written as in `[keybindings]`, and may not be ones an action is bound
+/// to.
+#[derive(Clone, Default)]
+pub struct Commands {
+    map: HashMap<Key, String>,
+}
+
+impl Commands {
+    pub fn command(&self, key: &Key) -> Option<&str> {
+        self.map.get(key).map(String::as_str)
+    }
+
+    /// Leaves out the commands on keys that `keybindings` has an action
+    /// on, returning a description of each to report at startup.
+    pub fn reject_bound(&mut self, keybindings: &Keybindings) -> Vec<String> {
+        let mut rejected = Vec::new();
+        self.map.retain(|key, _| match keybindings.action(key) {
+            Some(action) => {
+                rejected.push(format!(
+                    "`{}` is bound to {action}, ignoring the command on it",
+                    key_name(key)
+                ));
+                false
+            }
+            None => true,
+        });
+        rejected.sort();
+        rejected
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, _, _) = ACTIONS
+            .iter()
+            .find(|(_, action, _)| action == self)
+            .unwrap();
+        f.write_str(name)
+    }
+}
+
+impl<'de> Deserialize<'de> for Keybindings {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Keys {
+            One(String),
+            Many(Vec<String>),
+        }
+
+        let table = toml::Table::deserialize(deserializer)?;
+        let mut bindings = Self::default();
+        for (name, value) in table {
+            let action = ACTIONS
+                .iter()
+                .find(|(action_name, _, _)| *action_name == name)
+                .map(|&(_, action, _)| action)
+                .ok_or_else(|| de::Error::custom(format!("unknown action `{name}`")))?;
+            let names = match Keys::deserialize(value).map_err(de::Error::custom)? {
+                Keys::One(name) => vec![name],
+                Keys::Many(names) => names,
+            };
+            let keys = names
+                .iter()
+                .map(|name| {
+                    parse_key(name)
+                        .ok_or_else(|| de::Error::custom(format!("unknown key `{name}`")))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            bindings.bind(action, &keys);
+        }
+        Ok(bindings)
+    }
+}
+
+impl<'de> Deserialize<'de> for Commands {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let table = HashMap::<String, String>::deserialize(deserializer)?;
+        let map = table
+            .into_iter()
+            .map(|(name, command)| match parse_key(&name) {
+                Some(key) => Ok((key, command)),
+                None => Err(de::Error::custom(format!("unknown key `{name}`"))),
+            })
+            .collect::<Result<_, D::Error>>()?;
+        Ok(Self { map })
+    }
+}
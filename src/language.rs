@@ -0,0 +1,145 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use tree_sitter::Language;
+
+/// The grammars tree-viz can parse with, by name and by file extension.
+/// Names are matched in registration order.
+#[derive(Clone)]
+pub struct Registry {
+    languages: Vec<(String, Language)>,
+    extensions: Vec<(String, String)>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl Registry {
+    pub fn empty() -> Self {
+        Self {
+            languages: Vec::new(),
+            extensions: Vec::new(),
+        }
+    }
+
+    /// The grammars compiled into tree-viz.
+    pub fn builtin() -> Self {
+        let mut registry = Self::empty();
+        registry
+            .register("rust", tree_sitter_rust::language(), &["rs"])
+            .register("tsx", tree_sitter_typescript::language_tsx(), &["tsx"])
+            .register(
+                "typescript",
+                tree_sitter_typescript::language_tsx(),
+                &["ts"],
+            )
+            .register(
+                "javascript",
+                tree_sitter_javascript::language(),
+                &["js", "jsx", "mjs", "cjs"],
+            )
+            .register("python", tree_sitter_python::language(), &["py"])
+            .register("ruby", tree_sitter_ruby::language(), &["rb"])
+            .register("markdown", tree_sitter_md::language(), &["md"]);
+        registry
+    }
+
+    /// Adds a grammar, or replaces the one registered under `name`, and
+    /// claims the given file extensions for it.
+    pub fn register(&mut self, name: &str, language: Language, extensions: &[&str]) -> &mut Self {
+        match self.languages.iter_mut().find(|(n, _)| n == name) {
+            Some((_, existing)) => *existing = language,
+            None => self.languages.push((name.to_owned(), language)),
+        }
+        for extension in extensions {
+            self.extensions.retain(|(e, _)| e != extension);
+            self.extensions
+                .push(((*extension).to_owned(), name.to_owned()));
+        }
+        self
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Language)> {
+        self.languages
+            .iter()
+            .map(|(name, language)| (name.as_str(), language))
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<Language> {
+        self.languages
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, language)| language.clone())
+    }
+
+    pub fn is_keyword(&self, arg: &str) -> bool {
+        self.languages.iter().any(|(n, _)| n == arg)
+    }
+
+    /// Picks a grammar from the file extension, returning it along with its
+    /// language name.
+    pub fn detect(&self, path: &Path) -> Option<(&str, Language)> {
+        let name = self.name_for(path)?;
+        Some((name, self.by_name(name)?))
+    }
+
+    fn name_for(&self, path: &Path) -> Option<&str> {
+        let extension = path.extension()?.to_str()?;
+        let (_, name) = self.extensions.iter().find(|(e, _)| e == extension)?;
+        Some(name)
+    }
+
+    /// The name registered after `name`, wrapping around to the first.
+    pub fn next_after(&self, name: &str) -> Option<(&str, Language)> {
+        let current = self.languages.iter().position(|(n, _)| n == name);
+        let next = current.map_or(0, |i| (i + 1) % self.languages.len());
+        let (name, language) = self.languages.get(next)?;
+        Some((name, language.clone()))
+    }
+
+    pub fn names(&self) -> String {
+        self.languages
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Files below `dir` with an extension of `language`, or of any
+    /// registered language, in path order. Hidden files and directories
+    /// are skipped, and symlinks to directories are not followed, so a link
+    /// back up the tree cannot loop.
+    pub fn source_files(&self, dir: &Path, language: Option<&str>) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        self.collect(dir, language, &mut files);
+        files.sort();
+        files
+    }
+
+    fn collect(&self, dir: &Path, language: Option<&str>, files: &mut Vec<PathBuf>) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                self.collect(&path, language, files);
+                continue;
+            }
+            let matches = self
+                .name_for(&path)
+                .is_some_and(|name| language.is_none_or(|l| l == name));
+            if matches {
+                files.push(path);
+            }
+        }
+    }
+}
@@ -0,0 +1,37 @@
+//! The tree-sitter syntax tree viewer behind the `tree-viz` binary.
+//!
+//! Build an [`App`] from source, a grammar, which a [`Registry`] can
+//! pick from the file extension, and any queries as [`QuerySource`]s.
+//! Adjust its [`Config`], or flip the [`Toggles`] with methods such as
+//! [`App::toggle_named_only`], and call [`App::render`] for the styled rows
+//! of the tree, or [`App::export`] for one of the [`export::Format`]s.
+//! [`App::render_plain`] lists every node in a fixed, uncolored format, for
+//! snapshot tests of a grammar or a query.
+
+mod app;
+mod config;
+pub mod export;
+mod language;
+mod stats;
+mod theme;
+
+pub use app::{App, AppError, QuerySource};
+pub use config::{Config, GuideStyle, RangeFormat, Toggles};
+pub use language::Registry;
+pub use theme::Theme;
+
+// what the `tree-viz` binary drives the viewer with. it has to be
+// reachable from there, but is not part of the api above and changes
+// whenever the viewer does
+#[doc(hidden)]
+pub mod keys;
+#[doc(hidden)]
+pub mod log;
+#[doc(hidden)]
+pub mod serve;
+#[doc(hidden)]
+pub mod session;
+#[doc(hidden)]
+pub use app::{decode, PromptKind, INLINE};
+#[doc(hidden)]
+pub use config::ConfigDir;
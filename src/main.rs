@@ -1,102 +1,719 @@
-mod app;
-mod config;
+mod cli;
 
 use std::{
-    env, fs,
-    path::Path,
-    sync::{mpsc, Arc, RwLock},
+    fs,
+    io::{self, Read as _, Write as _},
+    panic,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex, PoisonError,
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use app::App;
+use cli::Args;
 use console::{Key, Term};
-use notify::{Event as WatchEvent, EventKind as WatchEventKind, RecursiveMode, Watcher};
+use encoding_rs::Encoding;
+use notify::{
+    Event as WatchEvent, EventKind as WatchEventKind, RecommendedWatcher, RecursiveMode, Watcher,
+};
+use tree_sitter::{LANGUAGE_VERSION, MIN_COMPATIBLE_LANGUAGE_VERSION};
+use tree_viz::{
+    export, keys::Action, log, session::SessionStore, verbose, App, AppError, Config, ConfigDir,
+    PromptKind, QuerySource, Registry, INLINE,
+};
+
+// console has no api for the alternate screen buffer
+const ENTER_ALTERNATE_SCREEN: &str = "\x1b[?1049h";
+const LEAVE_ALTERNATE_SCREEN: &str = "\x1b[?1049l";
+// button presses and the wheel, reported as SGR sequences. terminals
+// without mouse reporting ignore these and keep sending keys only
+const ENABLE_MOUSE: &str = "\x1b[?1000h\x1b[?1006h";
+const DISABLE_MOUSE: &str = "\x1b[?1006l\x1b[?1000l";
+
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+// columns rows are laid out for when printing without `--width`
+const PRINTED_WIDTH: usize = 100;
+
+#[derive(Clone, Copy)]
+enum Changed {
+    Source,
+    Query,
+}
+
+enum Input {
+    Key(Key),
+    Mouse(Mouse),
+    // Ctrl-C, which quits as `Action::Quit` does
+    Interrupt,
+}
+
+// set by SIGINT while the screen is up
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+enum Mouse {
+    WheelUp,
+    WheelDown,
+    // 0-based screen row of a left click
+    Click(usize),
+}
 
 fn main() {
-    let mut args = env::args();
-    let _ = args.next();
-
-    let language = match args.next().as_ref().map(|s| s.as_str()) {
-        Some("rust") => tree_sitter_rust::language(),
-        Some("tsx") | Some("typescript") => tree_sitter_typescript::language_tsx(),
-        Some("javascript") => tree_sitter_javascript::language(),
-        Some("python") => tree_sitter_python::language(),
-        Some("ruby") => tree_sitter_ruby::language(),
-        Some("markdown") => tree_sitter_md::language(),
-        Some(s) => panic!("invalid language passed: {s}"),
-        None => panic!("no language passed"),
+    let mut cli = Args::parse().unwrap_or_else(|e| fail(&e));
+    log::set_verbose(cli.verbose);
+
+    if cli.about {
+        about();
+        return;
+    }
+    let config_dir = ConfigDir::resolve(cli.config_dir);
+    let config = Config::load(config_dir.as_ref());
+    let keybindings = config.keybindings.clone();
+    let commands = config.commands.clone();
+    // rejected up front rather than parsing text decoded the wrong way
+    let encoding = cli.encoding.as_deref().map(|label| {
+        if cli.code.is_some() {
+            fail("--encoding applies to files and stdin, not to --code");
+        }
+        Encoding::for_label(label.as_bytes())
+            .unwrap_or_else(|| fail(&format!("unknown encoding `{label}`")))
+    });
+    let registry = Registry::builtin();
+    let mut args = cli.positional.into_iter().peekable();
+
+    // an explicit language keyword comes first and overrides detection,
+    // anything else is the path to the source file, unless it was inline
+    let explicit = args.next_if(|arg| registry.is_keyword(arg));
+    let path = if cli.code.is_some() {
+        INLINE.to_owned()
+    } else {
+        match args.next() {
+            Some(path) => path,
+            None => fail("usage: tree-viz [language] <file> [scope_query...]"),
+        }
+    };
+    // a directory is browsed one file at a time, starting with the first
+    let files = if Path::new(&path).is_dir() {
+        let files = registry.source_files(Path::new(&path), explicit.as_deref());
+        if files.is_empty() {
+            fail(&format!("no source files found in `{path}`"));
+        }
+        files
+    } else {
+        Vec::new()
     };
-    let path = args.next().expect("no arg passed");
-    let query_path = args.next();
-    let src = fs::read_to_string(&path).expect("unable to read file");
-
-    let app = Arc::new(RwLock::new(App::new(
-        src.as_bytes(),
-        &path,
-        query_path.as_ref(),
-        language,
-    )));
-
-    let watch_fn = |watcher_app: Arc<RwLock<App>>| {
-        move |ev| {
-            if let Ok(WatchEvent {
-                kind: WatchEventKind::Modify(..),
-                ..
-            }) = ev
-            {
-                if let Ok(mut locked) = watcher_app.try_write() {
-                    locked.reload();
-                    locked.draw();
-                };
+    let path = files
+        .first()
+        .map_or(path, |file| file.to_string_lossy().into_owned());
+    let (language_name, language) = match explicit {
+        Some(name) => {
+            let language = registry.by_name(&name).unwrap();
+            (name, language)
+        }
+        None if cli.code.is_some() => fail(&format!(
+            "--code needs a language, one of: {}",
+            registry.names()
+        )),
+        None => match registry.detect(Path::new(&path)) {
+            Some((name, language)) => (name.to_owned(), language),
+            None => fail(&format!(
+                "unable to detect the language of `{path}`, pass one of: {}",
+                registry.names()
+            )),
+        },
+    };
+    // every query given is layered over the others, the default one is
+    // only used when none is
+    let mut query_sources: Vec<_> = cli
+        .query_string
+        .take()
+        .map(QuerySource::Inline)
+        .into_iter()
+        .collect();
+    let mut read_stdin = path == "-";
+    for arg in cli.query.drain(..).chain(args) {
+        if arg != "-" {
+            query_sources.push(QuerySource::Path(PathBuf::from(arg)));
+            continue;
+        }
+        if read_stdin {
+            fail("stdin can only be read once, for the source or one query");
+        }
+        read_stdin = true;
+        match io::read_to_string(io::stdin()) {
+            Ok(query) => query_sources.push(QuerySource::Inline(query)),
+            Err(e) => fail(&format!("unable to read the query from stdin: {e}")),
+        }
+    }
+    if query_sources.is_empty() {
+        query_sources.extend(
+            config_dir
+                .as_ref()
+                .and_then(|dir| dir.default_query(&language_name))
+                .map(QuerySource::Path),
+        );
+    }
+    // only query files can be watched
+    let query_paths: Vec<_> = query_sources
+        .iter()
+        .filter_map(|source| match source {
+            QuerySource::Path(path) => Some(path.clone()),
+            QuerySource::Inline(_) => None,
+        })
+        .collect();
+    // in directory mode the first file is opened as paging to it would be,
+    // so that one that cannot be shown is skipped like the others
+    let browsing = !files.is_empty();
+    // invalid UTF-8 is kept as is, tree-sitter parses bytes
+    let src = if let Some(code) = cli.code.take() {
+        Ok(code.into_bytes())
+    } else if browsing {
+        Ok(Vec::new())
+    } else if path == "-" {
+        let mut src = Vec::new();
+        io::stdin().read_to_end(&mut src).map(|_| src)
+    } else {
+        fs::read(&path)
+    };
+    let app = src
+        .map_err(|e| AppError::io(Path::new(&path), e))
+        .and_then(|src| match encoding {
+            Some(encoding) => {
+                let decoded = tree_viz::decode(Path::new(&path), &src, encoding)?;
+                Ok((decoded, Some((encoding, src))))
             }
+            None => Ok((src, None)),
+        })
+        .and_then(|(src, original)| {
+            let app = App::new(
+                &src,
+                Path::new(&path),
+                query_sources,
+                language,
+                &language_name,
+                config,
+            )?;
+            Ok(match original {
+                Some((encoding, original)) => app.with_encoding(encoding, original),
+                None => app,
+            })
+        })
+        .map(|app| app.with_registry(registry).with_files(files))
+        .map(|app| match cli.captures.take() {
+            Some(names) => app.with_shown_captures(names),
+            None => app,
+        })
+        .map(|app| match cli.width {
+            Some(columns) => app.with_width(columns),
+            None => app,
+        });
+    let mut app = match app {
+        Ok(app) => app,
+        Err(e) => fail(&e.to_string()),
+    };
+    if browsing && !app.open_first_file() {
+        fail("none of the source files found can be shown");
+    }
+    let mut app = match (cli.range, cli.lines) {
+        (Some((start, end)), _) => app.with_range(start..end),
+        (None, Some((first, last))) => app.with_lines(first, last),
+        (None, None) => app,
+    };
+    if let Some(kind) = &cli.select_kind {
+        if !app.select_kind(kind) {
+            fail(&format!(
+                "no node of kind `{kind}` in {}",
+                app.path().display()
+            ));
+        }
+    }
+
+    if cli.stats_only {
+        for line in app.stats_report() {
+            println!("{line}");
+        }
+        if let Some(peak) = peak_memory() {
+            println!("peak memory: {:.1} MiB", peak as f64 / (1024.0 * 1024.0));
+        }
+        return;
+    }
+
+    // compile errors have already failed above
+    if cli.check_query {
+        let Some((matches, counts)) = app.query_counts() else {
+            fail("--check-query needs a query");
+        };
+        println!("{matches} matches");
+        for (name, count) in counts {
+            println!("  @{name:<20} {count}");
+        }
+        return;
+    }
+
+    // answers follow the files as the viewer does, reloading between
+    // requests
+    if let Some(port) = cli.serve {
+        let (changes_tx, changes_rx) = mpsc::channel();
+        let _source_watcher = (!app.is_stdin() && !app.is_inline())
+            .then(|| watch(app.path(), changes_tx.clone(), Changed::Source))
+            .flatten();
+        let _query_watchers = watch_queries(&query_paths, &changes_tx);
+        let app = Arc::new(Mutex::new(app));
+        let reloading = Arc::clone(&app);
+        thread::spawn(move || {
+            while let Some(changed) = next_change(&changes_rx) {
+                let mut app = reloading.lock().unwrap_or_else(PoisonError::into_inner);
+                match changed {
+                    Changed::Source => app.reload(),
+                    Changed::Query => app.reload_query(),
+                }
+            }
+        });
+        if let Err(e) = tree_viz::serve::serve(app, port) {
+            fail(&format!("unable to serve on port {port}: {e}"));
+        }
+        return;
+    }
+
+    // printed output is laid out the same wherever it goes
+    let printed_width = cli.width.unwrap_or(PRINTED_WIDTH);
+    let lines = |app: &App| {
+        if cli.overlay {
+            app.overlay()
+        } else {
+            app.render()
         }
     };
+    if cli.export.is_some() || cli.html {
+        let app = app.with_width(printed_width);
+        // the html keeps the styling whatever stdout is
+        if cli.html {
+            console::set_colors_enabled(true);
+        }
+        let write = |out: &mut dyn io::Write| match cli.export {
+            Some(format) => app.export(format, out),
+            None => export::html(&lines(&app), out),
+        };
+        let result = match cli.output.as_deref() {
+            Some(out) if out != Path::new("-") => fs::File::create(out).and_then(|file| {
+                let mut out = io::BufWriter::new(file);
+                write(&mut out)?;
+                out.flush()
+            }),
+            _ => write(&mut io::stdout().lock()),
+        };
+        if let Err(e) = result {
+            eprintln!("export failed: {e}");
+            process::exit(1);
+        }
+        return;
+    }
 
-    let mut watcher1 = notify::recommended_watcher(watch_fn(Arc::clone(&app))).unwrap();
-    watcher1
-        .watch(Path::new(&path), RecursiveMode::NonRecursive)
-        .unwrap();
+    // there is no file to watch for inline source
+    if cli.once || path == INLINE {
+        let app = app.with_width(printed_width);
+        console::set_colors_enabled(Term::stdout().is_term());
+        for line in lines(&app) {
+            println!("{line}");
+        }
+        if cli.fail_on_error {
+            let problems = app.problem_locations();
+            if !problems.is_empty() {
+                for problem in problems {
+                    eprintln!("{problem}");
+                }
+                process::exit(2);
+            }
+        }
+        return;
+    }
 
-    let mut watcher2 = notify::recommended_watcher(watch_fn(Arc::clone(&app))).unwrap();
-    if let Some(query_path) = query_path {
-        watcher2
-            .watch(Path::new(&query_path), RecursiveMode::NonRecursive)
-            .unwrap();
+    // the view is left as it was the last time this file was open, unless
+    // a range or a node to look at was asked for
+    let sessions = (!app.is_stdin()).then(SessionStore::resolve).flatten();
+    let restore = cli.range.is_none() && cli.lines.is_none() && cli.select_kind.is_none();
+    if let Some(session) = sessions
+        .as_ref()
+        .filter(|_| restore)
+        .and_then(|store| store.load(app.path()))
+    {
+        app.restore(session);
     }
 
+    // editors often save in several steps, so events are only acted on
+    // once the files have been quiet for a while
+    let (changes_tx, changes_rx) = mpsc::channel();
+    // there is nothing to watch when reading from stdin
+    let mut watched = app.path().to_owned();
+    let mut _source_watcher = (path != "-")
+        .then(|| watch(&watched, changes_tx.clone(), Changed::Source))
+        .flatten();
+    let _query_watchers = watch_queries(&query_paths, &changes_tx);
+
+    // whether a key quits depends on the app state, so the main thread
+    // acknowledges each key and the reader stops as soon as it is told to,
+    // so that the terminal is not put back into raw mode while exiting
     let (tx, rx) = mpsc::channel();
-    let tx0 = tx.clone();
+    let (ack_tx, ack_rx) = mpsc::channel();
     thread::spawn(move || {
         let term = Term::stdout();
         loop {
-            if let Ok(Key::Char(ev)) = term.read_key() {
-                tx0.send(ev).unwrap();
+            let input = match term.read_key() {
+                // console stops reading at the `<` of an SGR mouse report
+                Ok(Key::UnknownEscSeq(seq)) if seq == ['[', '<'] => {
+                    read_mouse(&term).map(Input::Mouse)
+                }
+                Ok(key) => Some(Input::Key(key)),
+                // console raises SIGINT itself on reading Ctrl-C, and then
+                // fails the read
+                Err(_) if INTERRUPTED.load(Ordering::Relaxed) => Some(Input::Interrupt),
+                Err(_) => None,
+            };
+            if let Some(input) = input {
+                tx.send(input).unwrap();
+                if !ack_rx.recv().unwrap_or(false) {
+                    break;
+                }
             }
         }
     });
 
-    if let Ok(locked) = app.try_read() {
-        locked.draw();
-    }
+    let term = Term::stdout();
+    let screen = Screen::enter(&term);
+    app.draw();
 
+    // when each file last changed, while a reload of it is pending
+    let mut pending_source: Option<Instant> = None;
+    let mut pending_query: Option<Instant> = None;
+    // console has no resize events, so the size is polled
+    let mut size = term.size();
+    // the action before, for the two key `gg`
+    let mut last_action = None;
     loop {
+        if term.size() != size {
+            size = term.size();
+            app.draw();
+        }
+
+        while let Ok(changed) = changes_rx.try_recv() {
+            match changed {
+                Changed::Source => pending_source = Some(Instant::now()),
+                Changed::Query => pending_query = Some(Instant::now()),
+            }
+        }
+        if pending_source.is_some_and(|last| last.elapsed() >= DEBOUNCE) {
+            // a full reload re-reads the query too
+            pending_source = None;
+            pending_query = None;
+            app.reload();
+            app.draw();
+        }
+        if pending_query.is_some_and(|last| last.elapsed() >= DEBOUNCE) {
+            pending_query = None;
+            app.reload_query();
+            app.draw();
+        }
+
         match rx.try_recv() {
-            Ok(ev) => {
-                if let Ok(mut locked) = app.try_write() {
-                    match ev {
-                        '>' => locked.increase_indent(),
-                        '<' => locked.decrease_indent(),
-                        'n' => locked.toggle_ranges(),
-                        's' => locked.toggle_source(),
-                        'r' => locked.reload(),
-                        _ => (),
+            Ok(Input::Key(key)) if app.is_prompting() => {
+                app.prompt_key(key);
+                app.draw();
+                ack_tx.send(true).unwrap();
+            }
+            // the reader waits for this key to be acknowledged, so the
+            // command reads from the terminal alone
+            Ok(Input::Key(key)) if commands.command(&key).is_some() => {
+                let command = commands.command(&key).unwrap();
+                screen.suspend(&term, || app.run_command(command));
+                // a Ctrl-C while it ran was meant for the command
+                INTERRUPTED.store(false, Ordering::Relaxed);
+                app.draw();
+                ack_tx.send(true).unwrap();
+            }
+            Ok(Input::Key(key)) if keybindings.action(&key) == Some(Action::Quit) => {
+                let _ = ack_tx.send(false);
+                break;
+            }
+            Ok(Input::Interrupt) => {
+                let _ = ack_tx.send(false);
+                break;
+            }
+            Ok(Input::Mouse(mouse)) => {
+                match mouse {
+                    Mouse::WheelUp => app.scroll_up(3),
+                    Mouse::WheelDown => app.scroll_down(3),
+                    Mouse::Click(row) => app.select_row(row),
+                }
+                app.draw();
+                ack_tx.send(true).unwrap();
+            }
+            Ok(Input::Key(key)) => {
+                let action = keybindings.action(&key);
+                let previous = std::mem::replace(&mut last_action, action);
+                match action {
+                    Some(Action::SelectRoot) if previous == Some(Action::SelectRoot) => {
+                        app.select_root();
+                        last_action = None;
                     }
-                    locked.draw();
+                    Some(action) => dispatch(&mut app, action),
+                    None => (),
                 }
+                // in directory mode the watch follows the file shown
+                if app.path() != watched {
+                    watched = app.path().to_owned();
+                    _source_watcher = watch(&watched, changes_tx.clone(), Changed::Source);
+                }
+                app.draw();
+                ack_tx.send(true).unwrap();
             }
             _ => (),
         }
         thread::sleep(Duration::from_millis(10));
     }
+
+    drop(screen);
+
+    if let Some(store) = &sessions {
+        if let Err(e) = store.save(app.path(), &app.session()) {
+            eprintln!("tree-viz: unable to save the session: {e}");
+        }
+    }
+}
+
+// the alternate screen with mouse reporting on, for as long as it is
+// held. the terminal is put back when it is dropped, and by a panic
+// before the message is printed, so that it is not lost with the screen
+struct Screen;
+
+impl Screen {
+    fn enter(term: &Term) -> Self {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            leave_screen(&Term::stdout());
+            previous(info);
+        }));
+        catch_interrupt();
+        term.write_str(ENTER_ALTERNATE_SCREEN).unwrap();
+        term.write_str(ENABLE_MOUSE).unwrap();
+        Screen
+    }
+
+    // the normal screen while `f` runs, for a command to draw on
+    fn suspend<T>(&self, term: &Term, f: impl FnOnce() -> T) -> T {
+        leave_screen(term);
+        let result = f();
+        let _ = term.write_str(ENTER_ALTERNATE_SCREEN);
+        let _ = term.write_str(ENABLE_MOUSE);
+        result
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        leave_screen(&Term::stdout());
+    }
+}
+
+// by default SIGINT ends the process on the spot, without dropping the
+// screen or saving the session, so it only flags the key reader instead.
+// no SA_RESTART, so that a read in progress is cut short by it
+#[cfg(unix)]
+fn catch_interrupt() {
+    extern "C" fn on_interrupt(_: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::Relaxed);
+    }
+    // SAFETY: the handler only stores to an atomic, which is
+    // async-signal-safe
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut());
+    }
+}
+
+#[cfg(not(unix))]
+fn catch_interrupt() {}
+
+// leaving more than once does no harm
+fn leave_screen(term: &Term) {
+    let _ = term.write_str(DISABLE_MOUSE);
+    let _ = term.write_str(LEAVE_ALTERNATE_SCREEN);
+    let _ = term.show_cursor();
+}
+
+// the rest of a `\x1b[<button;column;row` report, ending in `M` for a
+// press and `m` for a release. only wheel turns and left clicks are kept
+fn read_mouse(term: &Term) -> Option<Mouse> {
+    let mut report = String::new();
+    let pressed = loop {
+        match term.read_char().ok()? {
+            'M' => break true,
+            'm' => break false,
+            c if c.is_ascii_digit() || c == ';' => report.push(c),
+            _ => return None,
+        }
+    };
+    let mut fields = report.split(';').map(|field| field.parse::<usize>().ok());
+    let (button, _column, row) = (fields.next()??, fields.next()??, fields.next()??);
+    match button {
+        64 => Some(Mouse::WheelUp),
+        65 => Some(Mouse::WheelDown),
+        0 if pressed => Some(Mouse::Click(row.checked_sub(1)?)),
+        _ => None,
+    }
+}
+
+// peak resident set size in bytes, where the platform reports it
+fn peak_memory() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+// editors that save by renaming a temporary file over the original
+// break a watch on the file itself, so the directory is watched instead
+fn watch(
+    path: &Path,
+    changes: mpsc::Sender<Changed>,
+    changed: Changed,
+) -> Option<RecommendedWatcher> {
+    let (dir, target) = watch_target(path)?;
+    let handler = move |ev: notify::Result<WatchEvent>| {
+        match &ev {
+            Ok(event) => verbose!("watcher fired: {:?} {:?}", event.kind, event.paths),
+            Err(e) => verbose!("watcher error: {e}"),
+        }
+        if let Ok(WatchEvent {
+            kind:
+                WatchEventKind::Modify(..) | WatchEventKind::Create(..) | WatchEventKind::Remove(..),
+            paths,
+            ..
+        }) = ev
+        {
+            if paths.contains(&target) {
+                let _ = changes.send(changed);
+            }
+        }
+    };
+    let mut watcher = notify::recommended_watcher(handler).ok()?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive).ok()?;
+    Some(watcher)
+}
+
+fn watch_queries(paths: &[PathBuf], changes: &mpsc::Sender<Changed>) -> Vec<RecommendedWatcher> {
+    paths
+        .iter()
+        .filter_map(|path| watch(path, changes.clone(), Changed::Query))
+        .collect()
+}
+
+// the next change once the files have been quiet for a while, a source
+// change standing for a query change too as a reload re-reads both.
+// `None` once there are no watchers left
+fn next_change(changes: &mpsc::Receiver<Changed>) -> Option<Changed> {
+    let mut changed = changes.recv().ok()?;
+    while let Ok(next) = changes.recv_timeout(DEBOUNCE) {
+        if let Changed::Source = next {
+            changed = next;
+        }
+    }
+    Some(changed)
+}
+
+// the directory containing `path`, and `path` as the watcher reports it
+fn watch_target(path: &Path) -> Option<(PathBuf, PathBuf)> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let dir = fs::canonicalize(dir).ok()?;
+    let target = dir.join(path.file_name()?);
+    Some((dir, target))
+}
+
+// actions that need more than the app, like quitting and the two key
+// `gg`, are handled by the main loop
+fn dispatch(app: &mut App, action: Action) {
+    match action {
+        Action::Quit | Action::SelectRoot => (),
+        Action::Search => app.open_prompt(PromptKind::Search),
+        Action::EditQuery => app.open_prompt(PromptKind::Query),
+        Action::Goto => app.open_prompt(PromptKind::Goto),
+        Action::Pin => app.open_prompt(PromptKind::Pin),
+        Action::SelectLast => app.select_last(),
+        Action::SelectParent => app.select_parent(),
+        Action::SelectFirstChild => app.select_first_child(),
+        Action::SelectNextSibling => app.select_next_sibling(),
+        Action::SelectPrevSibling => app.select_prev_sibling(),
+        Action::SelectNext => app.select_next(),
+        Action::SelectPrev => app.select_prev(),
+        Action::NextMatch if app.is_searching() => app.next_match(),
+        Action::NextMatch | Action::CycleRanges => app.cycle_ranges(),
+        Action::PrevMatch => app.prev_match(),
+        Action::NextError => app.next_error(),
+        Action::ToggleStats => app.toggle_stats(),
+        Action::ToggleSexp => app.toggle_sexp(),
+        Action::ToggleOverlay => app.toggle_overlay(),
+        Action::ToggleHelp => app.toggle_help(),
+        Action::ToggleTimings => app.toggle_timings(),
+        Action::CycleLanguage => app.cycle_language(),
+        Action::IncreaseIndent => app.increase_indent(),
+        Action::DecreaseIndent => app.decrease_indent(),
+        Action::CycleGuides => app.cycle_guides(),
+        Action::ToggleDepthGuides => app.toggle_depth_guides(),
+        Action::IncreaseMaxDepth => app.increase_max_depth(),
+        Action::DecreaseMaxDepth => app.decrease_max_depth(),
+        Action::ToggleSource => app.toggle_source(),
+        Action::ToggleWrapSource => app.toggle_wrap_source(),
+        Action::ToggleSourceLine => app.toggle_source_line(),
+        Action::ToggleGutter => app.toggle_gutter(),
+        Action::ShowLocation => app.show_location(),
+        Action::CopySelected => app.copy_selected(),
+        Action::ToggleFieldNames => app.toggle_field_names(),
+        Action::ToggleNodeFlags => app.toggle_node_flags(),
+        Action::ToggleSiblingIndex => app.toggle_sibling_index(),
+        Action::ToggleNamedOnly => app.toggle_named_only(),
+        Action::ToggleAttachExtras => app.toggle_attach_extras(),
+        Action::ToggleCollapseRepeats => app.toggle_collapse_repeats(),
+        Action::ToggleCapturesOnly => app.toggle_captures_only(),
+        Action::ToggleAnonymousText => app.toggle_anonymous_text(),
+        Action::CycleShownCaptures => app.cycle_shown_captures(),
+        Action::ToggleCollapse => app.toggle_collapse(),
+        Action::ToggleFocus => app.toggle_focus(),
+        Action::Reload => app.reload(),
+        Action::ExportSexp => app.export_to_file(export::Format::Sexp),
+        Action::ExportJson => app.export_to_file(export::Format::Json),
+        Action::ExportDot => app.export_to_file(export::Format::Dot),
+        Action::ToggleSubtreeExport => app.toggle_subtree_export(),
+        Action::ScrollDown => app.scroll_down(1),
+        Action::ScrollUp => app.scroll_up(1),
+        Action::ScrollLeft => app.scroll_left(4),
+        Action::ScrollRight => app.scroll_right(4),
+        Action::PageDown => app.page_down(),
+        Action::PageUp => app.page_up(),
+        Action::NextFile => app.next_file(),
+        Action::PrevFile => app.prev_file(),
+        Action::ToggleSkipped => app.toggle_skipped(),
+    }
+}
+
+fn about() {
+    println!("tree-viz {}", env!("CARGO_PKG_VERSION"));
+    println!("tree-sitter {}", env!("TREE_SITTER_VERSION"));
+    println!("tree-sitter language ABI {MIN_COMPATIBLE_LANGUAGE_VERSION} to {LANGUAGE_VERSION}");
+    println!("grammars:");
+    for (name, language) in Registry::builtin().iter() {
+        println!("  {name:<12} abi {}", language.version());
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("tree-viz: {message}");
+    process::exit(1);
 }
@@ -0,0 +1,88 @@
+//! `--serve PORT`: answers requests about the tree over a local TCP
+//! socket, for editor plugins that want the parse without the viewer.
+//!
+//! Requests and responses are one line each, every response is a JSON
+//! value:
+//!
+//! - `tree`: the whole tree, as `--export json` writes it
+//! - a byte offset such as `120`, or a 1-based `line:column` such as
+//!   `12:5`: the smallest node there, with the kinds from the root down to
+//!   it in `path`
+//! - `reload`: re-reads the file, `{"reloaded": true}` once it parses.
+//!   Source from stdin or `--code` is left as it is.
+//!
+//! Anything else, or a failed reload, is answered with `{"error": "..."}`.
+//! Each connection is served on its own thread, a request holding the app
+//! only while it is answered, so one client cannot keep the others waiting.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex, PoisonError},
+    thread,
+};
+
+use serde_json::{json, Value};
+
+use crate::{verbose, App};
+
+/// Listens on `127.0.0.1:port` until the process is killed. Others may
+/// hold `app` too, e.g. to reload it when the file changes.
+pub fn serve(app: Arc<Mutex<App>>, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("tree-viz: listening on {}", listener.local_addr()?);
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                verbose!("connection failed: {e}");
+                continue;
+            }
+        };
+        let app = Arc::clone(&app);
+        // a client going away only ends its own connection
+        thread::spawn(move || {
+            if let Err(e) = connection(&app, stream) {
+                verbose!("connection closed: {e}");
+            }
+        });
+    }
+    Ok(())
+}
+
+fn connection(app: &Mutex<App>, stream: TcpStream) -> io::Result<()> {
+    verbose!("connection from {}", stream.peer_addr()?);
+    let mut out = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        // a panic answering another client ends only that connection
+        let response = respond(
+            &mut app.lock().unwrap_or_else(PoisonError::into_inner),
+            line.trim(),
+        );
+        serde_json::to_writer(&mut out, &response)?;
+        writeln!(out)?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+fn respond(app: &mut App, request: &str) -> Value {
+    match request {
+        "tree" => app.json_tree(),
+        "reload" => {
+            app.reload();
+            match app.status() {
+                Some(status) => json!({ "error": status }),
+                None => json!({ "reloaded": true }),
+            }
+        }
+        position => app.json_node_at(position).unwrap_or_else(|| {
+            json!({
+                "error": format!(
+                    "expected `tree`, `reload`, a byte offset or line:column, got `{position}`"
+                )
+            })
+        }),
+    }
+}
@@ -0,0 +1,71 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{app::View, config::Toggles, export::fnv1a, verbose};
+
+/// A node as byte range and kind id, looked up again in the next parse
+/// and dropped if nothing there matches.
+pub type SavedNode = (usize, usize, u16);
+
+/// What the viewer showed of one file when it was closed: the toggles,
+/// the scroll position, the selection and the collapsed nodes.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    /// node kind ids only mean something to the grammar they came from
+    pub language: String,
+    pub scroll: usize,
+    pub hscroll: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selected: Option<SavedNode>,
+    pub collapsed: Vec<SavedNode>,
+    pub view: View,
+    pub show_help: bool,
+    pub show_timings: bool,
+    pub toggles: Toggles,
+}
+
+/// Sessions saved under `$XDG_CACHE_HOME/tree-viz/sessions`, falling back
+/// to `~/.cache/tree-viz/sessions`, one file per source file named after
+/// a hash of its absolute path.
+pub struct SessionStore {
+    dir: PathBuf,
+}
+
+impl SessionStore {
+    pub fn resolve() -> Option<Self> {
+        let base = env::var_os("XDG_CACHE_HOME")
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+        Some(Self {
+            dir: base.join("tree-viz").join("sessions"),
+        })
+    }
+
+    fn file_for(&self, path: &Path) -> io::Result<PathBuf> {
+        let path = fs::canonicalize(path)?;
+        let hash = fnv1a(path.as_os_str().as_encoded_bytes());
+        Ok(self.dir.join(format!("{hash:016x}.toml")))
+    }
+
+    /// The session last saved for `path`, if there is a readable one.
+    pub fn load(&self, path: &Path) -> Option<Session> {
+        let file = self.file_for(path).ok()?;
+        let src = fs::read_to_string(&file).ok()?;
+        toml::from_str(&src)
+            .inspect_err(|e| verbose!("ignoring session {}: {e}", file.display()))
+            .ok()
+    }
+
+    pub fn save(&self, path: &Path, session: &Session) -> io::Result<()> {
+        let file = self.file_for(path)?;
+        let src = toml::to_string(session).map_err(io::Error::other)?;
+        fs::create_dir_all(&self.dir)?;
+        fs::write(file, src)
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+
+use console::style;
+use tree_sitter::Tree;
+
+/// Node counts for a whole tree, gathered in a single walk.
+pub struct Stats {
+    pub nodes: usize,
+    pub max_depth: usize,
+    /// most frequent first, ties in name order
+    pub kinds: Vec<(&'static str, usize)>,
+}
+
+impl Stats {
+    pub fn of(tree: &Tree) -> Self {
+        let mut counts: HashMap<&'static str, usize> = HashMap::new();
+        let mut nodes = 0;
+        let mut max_depth = 0;
+
+        let mut cursor = tree.walk();
+        let mut depth = 0;
+        'walk: loop {
+            nodes += 1;
+            max_depth = max_depth.max(depth);
+            *counts.entry(cursor.node().kind()).or_default() += 1;
+
+            if cursor.goto_first_child() {
+                depth += 1;
+                continue;
+            }
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    break 'walk;
+                }
+                depth -= 1;
+            }
+        }
+
+        let mut kinds: Vec<_> = counts.into_iter().collect();
+        kinds.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        Self {
+            nodes,
+            max_depth,
+            kinds,
+        }
+    }
+
+    /// A summary line followed by at most `limit` kinds, with bars scaled
+    /// to fit in `width` columns.
+    pub fn lines(&self, width: usize, limit: usize) -> Vec<String> {
+        let mut lines = vec![format!(
+            "{} nodes, {} kinds, max depth {}",
+            self.nodes,
+            self.kinds.len(),
+            self.max_depth
+        )];
+        let shown = &self.kinds[..self.kinds.len().min(limit)];
+        let name_width = shown.iter().map(|(kind, _)| kind.len()).max().unwrap_or(0);
+        let count_width = shown
+            .first()
+            .map_or(1, |(_, count)| count.to_string().len());
+        let bar_width = width.saturating_sub(name_width + count_width + 2);
+        let most = shown.first().map_or(1, |&(_, count)| count);
+        for (kind, count) in shown {
+            let bar = "█".repeat(count * bar_width / most);
+            lines.push(format!(
+                "{kind:<name_width$} {count:>count_width$} {}",
+                style(bar).cyan()
+            ));
+        }
+        lines
+    }
+}
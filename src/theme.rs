@@ -0,0 +1,112 @@
+use console::{Color, Style};
+use serde::{de, Deserialize, Deserializer};
+
+use crate::export::fnv1a;
+
+/// Styles for each part of a row. In `config.toml` these go in a `[theme]`
+/// table as dotted style strings such as `"red.bold"`, and `captures` is a
+/// list of color names or 256-color numbers.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    #[serde(deserialize_with = "style")]
+    pub error: Style,
+    #[serde(deserialize_with = "style")]
+    pub field: Style,
+    #[serde(deserialize_with = "style")]
+    pub guide: Style,
+    #[serde(deserialize_with = "style")]
+    pub range: Style,
+    #[serde(deserialize_with = "style")]
+    pub source: Style,
+    /// kinds of the nodes a reload added, for a few redraws
+    #[serde(deserialize_with = "style")]
+    pub changed: Style,
+    /// the rows of the selected node, in place of every other style on
+    /// them, `error` and capture backgrounds included. on other rows an
+    /// `ERROR` kind is `error` over the background of the capture it is in
+    #[serde(deserialize_with = "style")]
+    pub selected: Style,
+    /// each capture name is hashed into this palette, for its `@name`
+    /// label and the background of the captured nodes
+    #[serde(deserialize_with = "colors")]
+    pub captures: Vec<Color>,
+    /// guide colors by depth, repeating, when `depth_guides` is on
+    #[serde(deserialize_with = "colors")]
+    pub depths: Vec<Color>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            error: Style::new().red(),
+            field: Style::new().yellow(),
+            guide: Style::new().bright().black(),
+            range: Style::new().bright().black(),
+            source: Style::new().cyan(),
+            changed: Style::new().green().bold(),
+            selected: Style::new().reverse(),
+            captures: vec![
+                Color::Yellow,
+                Color::Green,
+                Color::Cyan,
+                Color::Blue,
+                Color::Magenta,
+                Color::Color256(208),
+                Color::Color256(141),
+                Color::Color256(114),
+            ],
+            depths: vec![
+                Color::Blue,
+                Color::Magenta,
+                Color::Cyan,
+                Color::Green,
+                Color::Yellow,
+            ],
+        }
+    }
+}
+
+impl Theme {
+    // the same capture name always gets the same color
+    pub fn capture_color(&self, name: &str) -> Color {
+        match self.captures.len() {
+            0 => Color::Yellow,
+            len => self.captures[(fnv1a(name.as_bytes()) % len as u64) as usize],
+        }
+    }
+
+    pub fn depth_color(&self, depth: usize) -> Color {
+        match self.depths.len() {
+            0 => Color::Black,
+            len => self.depths[depth % len],
+        }
+    }
+}
+
+fn style<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Style, D::Error> {
+    let dotted = String::deserialize(deserializer)?;
+    Ok(Style::from_dotted_str(&dotted))
+}
+
+fn colors<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Color>, D::Error> {
+    let names = Vec::<String>::deserialize(deserializer)?;
+    names
+        .iter()
+        .map(|name| color(name).ok_or_else(|| de::Error::custom(format!("unknown color `{name}`"))))
+        .collect()
+}
+
+fn color(name: &str) -> Option<Color> {
+    Some(match name {
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "white" => Color::White,
+        _ => Color::Color256(name.parse().ok()?),
+    })
+}